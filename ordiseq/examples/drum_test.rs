@@ -14,7 +14,7 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
         4,   // 4 pulses
         0,   // No rotation
         0.8, // Velocity of 80%
-    );
+    )?;
 
     // seq.add_track(
     //     "Snare Drum",
@@ -33,7 +33,7 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     //     0.6, // Velocity of 60%
     // );
 
-    seq.to_midi().save(&make_filename(&seq.title(), "mid"))?;
+    seq.to_midi().save(make_filename(&seq.title(), "mid"))?;
 
     Ok(())
 }
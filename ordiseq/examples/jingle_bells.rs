@@ -50,7 +50,7 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     seq = seq.transpose(7)?;
 
     // Write the MIDI file output:
-    seq.to_midi().save(&make_filename(&seq.title(), "mid"))?;
+    seq.to_midi().save(make_filename(&seq.title(), "mid"))?;
 
     Ok(())
 }
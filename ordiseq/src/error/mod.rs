@@ -4,6 +4,49 @@ use thiserror::Error;
 pub enum OrdiseqError {
     #[error("Invalid time signature: {0}")]
     InvalidTimeSignature(String),
-    #[error("Chord transposition is not supported yet")]
-    ChordTranspositionUnsupported,
+    #[error("Invalid groove: {0}")]
+    InvalidGroove(String),
+    #[error("Invalid note value: {0}")]
+    InvalidNoteValue(String),
+    #[error(
+        "Note value {note_value} cannot be represented exactly at {ticks_per_quarter_note} ticks per quarter note"
+    )]
+    InexactNoteValue {
+        note_value: String,
+        ticks_per_quarter_note: u32,
+    },
+    #[error("Invalid position: {0}")]
+    InvalidPosition(String),
+    #[error("Invalid resolution: {0} ticks per quarter note")]
+    InvalidResolution(u32),
+    #[error("Invalid tempo: {0}")]
+    InvalidTempo(String),
+    #[error("Invalid timecode: {0}")]
+    InvalidTimecode(String),
+    #[error("Invalid cycle: {0}")]
+    InvalidCycle(String),
+    #[error("Invalid MIDI channel: {0} (expected 0-15)")]
+    InvalidChannel(u8),
+    #[error("Invalid program: {0}")]
+    InvalidProgram(String),
+    #[error("Invalid automation: {0}")]
+    InvalidAutomation(String),
+    #[error("No drum track for {0}")]
+    UnknownDrumTrack(String),
+    #[error("Invalid edit: {0}")]
+    InvalidEdit(String),
+    #[error("Invalid scale: {0}")]
+    InvalidScale(String),
+    #[error("Note out of scale: {0}")]
+    NoteOutOfScale(String),
+    #[error("Note out of range: {0}")]
+    NoteOutOfRange(String),
+    #[error("{} notes out of range", .0.len())]
+    NotesOutOfRange(Vec<crate::sequence::OutOfRangeNote>),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("MIDI error: {0}")]
+    Midi(#[from] midly::Error),
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
 }
@@ -0,0 +1,304 @@
+//! # Groove
+//!
+//! Swing and groove templates, applied to sequences when they are
+//! rendered to MIDI.
+//!
+//! A `Groove` divides time into a grid of note values, and gives each
+//! slot of a repeating pattern of grid steps a timing and velocity
+//! offset. Grooves can be extracted from existing MIDI files, and
+//! saved to and loaded from YAML.
+
+use crate::error::OrdiseqError;
+use crate::time::{Division, NoteValue, Time, TimeDelta};
+use midly::{MidiMessage, Smf, Timing, TrackEventKind};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The grid on which swing is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwingGrid {
+    Eighth,
+    Sixteenth,
+}
+
+/// The timing and velocity offsets of one slot of a groove.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct GrooveSlot {
+    /// Timing offset as a fraction of one grid step.
+    pub timing: f32,
+    /// Velocity offset, on the same 0->1 scale as note velocities.
+    pub velocity: f32,
+}
+
+/// Represents a swing or groove template.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "GrooveTemplate")]
+pub struct Groove {
+    pub name: String,
+    /// The length of one grid step.
+    pub grid: NoteValue,
+    /// The offsets of each grid step, repeating every `slots.len()` steps.
+    slots: Vec<GrooveSlot>,
+}
+
+/// A groove as read from YAML, before its slots are checked.
+#[derive(Deserialize)]
+struct GrooveTemplate {
+    name: String,
+    grid: NoteValue,
+    slots: Vec<GrooveSlot>,
+}
+
+impl TryFrom<GrooveTemplate> for Groove {
+    type Error = OrdiseqError;
+
+    fn try_from(template: GrooveTemplate) -> Result<Self, Self::Error> {
+        Groove::new(&template.name, template.grid, template.slots)
+    }
+}
+
+impl Groove {
+    /// Creates a new groove with the given slots.
+    pub fn new(name: &str, grid: NoteValue, slots: Vec<GrooveSlot>) -> Result<Self, OrdiseqError> {
+        if slots.is_empty() {
+            return Err(OrdiseqError::InvalidGroove(
+                "A groove needs at least one slot".to_string(),
+            ));
+        }
+        Ok(Groove {
+            name: name.to_string(),
+            grid,
+            slots,
+        })
+    }
+
+    /// Creates an MPC style swing groove.
+    ///
+    /// The swing percentage is the position of every second grid step
+    /// within each pair of steps: 50% is straight, 66% is close to a
+    /// triplet feel, and 75% is the maximum (a dotted feel).
+    pub fn swing(percent: f32, grid: SwingGrid) -> Result<Self, OrdiseqError> {
+        if !(50.0..=75.0).contains(&percent) {
+            return Err(OrdiseqError::InvalidGroove(format!(
+                "Swing must be between 50% and 75%: {percent}"
+            )));
+        }
+        let division = match grid {
+            SwingGrid::Eighth => Division::Eighth,
+            SwingGrid::Sixteenth => Division::Sixteenth,
+        };
+        Groove::new(
+            &format!("Swing {percent}%"),
+            NoteValue::new(division),
+            vec![
+                GrooveSlot::default(),
+                GrooveSlot {
+                    timing: percent / 50.0 - 1.0,
+                    velocity: 0.0,
+                },
+            ],
+        )
+    }
+
+    /// Extracts a groove from the notes of a MIDI file.
+    ///
+    /// Every note is assigned to its nearest grid step. The timing
+    /// offset of each slot is the average distance of its notes from
+    /// the grid, and the velocity offset is the average velocity of
+    /// its notes compared to the average velocity of all notes.
+    pub fn from_midi(smf: &Smf, grid: NoteValue, steps: usize) -> Result<Self, OrdiseqError> {
+        let ticks_per_quarter_note = match smf.header.timing {
+            Timing::Metrical(ticks) => ticks.as_int() as u32,
+            Timing::Timecode(..) => {
+                return Err(OrdiseqError::InvalidGroove(
+                    "Grooves can only be extracted from metrical MIDI files".to_string(),
+                ))
+            }
+        };
+        if steps == 0 {
+            return Err(OrdiseqError::InvalidGroove(
+                "A groove needs at least one slot".to_string(),
+            ));
+        }
+        let step = grid.to_ticks(ticks_per_quarter_note)?.ticks as f32;
+
+        // (sum of timing offsets, sum of velocities, number of notes)
+        let mut sums = vec![(0.0f32, 0.0f32, 0u32); steps];
+        for track in &smf.tracks {
+            let mut ticks = 0u32;
+            for event in track {
+                ticks += event.delta.as_int();
+                if let TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { vel, .. },
+                    ..
+                } = event.kind
+                {
+                    if vel == 0 {
+                        continue;
+                    }
+                    let index = (ticks as f32 / step).round();
+                    let slot = &mut sums[index as usize % steps];
+                    slot.0 += (ticks as f32 - index * step) / step;
+                    slot.1 += vel.as_int() as f32 / 127.0;
+                    slot.2 += 1;
+                }
+            }
+        }
+
+        let count: u32 = sums.iter().map(|(_, _, count)| count).sum();
+        if count == 0 {
+            return Err(OrdiseqError::InvalidGroove(
+                "The MIDI file has no notes".to_string(),
+            ));
+        }
+        let average_velocity =
+            sums.iter().map(|(_, velocity, _)| velocity).sum::<f32>() / count as f32;
+
+        let slots = sums
+            .into_iter()
+            .map(|(timing, velocity, count)| match count {
+                0 => GrooveSlot::default(),
+                _ => GrooveSlot {
+                    timing: timing / count as f32,
+                    velocity: velocity / count as f32 - average_velocity,
+                },
+            })
+            .collect();
+        Groove::new("Extracted groove", grid, slots)
+    }
+
+    /// Extracts a groove from the notes of a MIDI file on disk.
+    pub fn from_midi_file<P: AsRef<Path>>(
+        path: P,
+        grid: NoteValue,
+        steps: usize,
+    ) -> Result<Self, OrdiseqError> {
+        let data = std::fs::read(path)?;
+        let smf = Smf::parse(&data)?;
+        Groove::from_midi(&smf, grid, steps)
+    }
+
+    pub fn to_yaml(&self) -> Result<String, OrdiseqError> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self, OrdiseqError> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// Saves the groove to a YAML file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), OrdiseqError> {
+        Ok(std::fs::write(path, self.to_yaml()?)?)
+    }
+
+    /// Loads a groove from a YAML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, OrdiseqError> {
+        Groove::from_yaml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Returns the offsets of each grid step. There is always at least
+    /// one slot.
+    pub fn slots(&self) -> &[GrooveSlot] {
+        &self.slots
+    }
+
+    /// Returns the start time and velocity of a note played with this
+    /// groove. The note is moved by the offsets of its nearest grid step.
+    pub fn apply(
+        &self,
+        time: Time,
+        velocity: f32,
+        ticks_per_quarter_note: u32,
+    ) -> Result<(Time, f32), OrdiseqError> {
+        let step = self.grid.to_ticks(ticks_per_quarter_note)?.ticks;
+        let index = (time.ticks as u64 + step as u64 / 2) / step as u64;
+        let slot = self.slots[(index % self.slots.len() as u64) as usize];
+
+        let offset = TimeDelta::new((slot.timing * step as f32).round() as i64);
+        let velocity = (velocity + slot.velocity).clamp(0.0, 1.0);
+        Ok((time.saturating_add(offset), velocity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequence::Sequence;
+    use crate::time::common_time;
+    use klib::core::note::C;
+
+    #[test]
+    fn test_swing() {
+        let groove = Groove::swing(66.0, SwingGrid::Eighth).unwrap();
+        let apply = |ticks| groove.apply(Time { ticks }, 0.5, 96).unwrap().0.ticks;
+        assert_eq!(apply(0), 0);
+        assert_eq!(apply(48), 48 + 15); // 66% of a quarter note
+        assert_eq!(apply(96), 96);
+        assert_eq!(apply(144), 144 + 15);
+        // Notes close to a grid step follow it:
+        assert_eq!(apply(50), 50 + 15);
+        // Notes moved past the largest time stay at it:
+        assert_eq!(apply(u32::MAX - 10), u32::MAX);
+    }
+
+    #[test]
+    fn test_invalid_swing() {
+        assert!(Groove::swing(40.0, SwingGrid::Sixteenth).is_err());
+        assert!(Groove::swing(80.0, SwingGrid::Sixteenth).is_err());
+    }
+
+    #[test]
+    fn test_velocity_offset() {
+        let groove = Groove::new(
+            "accent",
+            NoteValue::new(Division::Quarter),
+            vec![
+                GrooveSlot {
+                    timing: 0.0,
+                    velocity: 0.2,
+                },
+                GrooveSlot {
+                    timing: 0.0,
+                    velocity: -0.8,
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(groove.apply(Time { ticks: 0 }, 0.9, 96).unwrap().1, 1.0);
+        assert_eq!(groove.apply(Time { ticks: 96 }, 0.5, 96).unwrap().1, 0.0);
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let groove = Groove::swing(60.0, SwingGrid::Sixteenth).unwrap();
+        let yaml = groove.to_yaml().unwrap();
+        assert_eq!(Groove::from_yaml(&yaml).unwrap(), groove);
+    }
+
+    #[test]
+    fn test_yaml_without_slots() {
+        let yaml = "name: empty\ngrid:\n  division: Eighth\n  dots: 0\n  tuplet: null\nslots: []\n";
+        assert!(Groove::from_yaml(yaml).is_err());
+        assert!(serde_yaml::from_str::<Groove>(yaml).is_err());
+    }
+
+    #[test]
+    fn test_extract_from_midi() {
+        // Render a swung sequence, then extract the swing back out of it:
+        let mut seq = Sequence::new("swung", common_time()).unwrap();
+        for step in 0..8 {
+            let velocity = if step % 2 == 0 { 0.8 } else { 0.4 };
+            seq.add_note(Time { ticks: step * 48 }, C, velocity, Time { ticks: 24 })
+                .unwrap();
+        }
+        seq.set_groove(Groove::swing(62.5, SwingGrid::Eighth).unwrap())
+            .unwrap();
+        let smf = seq.to_midi();
+
+        let groove = Groove::from_midi(&smf, NoteValue::new(Division::Eighth), 2).unwrap();
+        assert_eq!(groove.slots()[0].timing, 0.0);
+        assert_eq!(groove.slots()[1].timing, 0.25);
+        assert!((groove.slots()[0].velocity - 0.2).abs() < 0.01);
+        assert!((groove.slots()[1].velocity + 0.2).abs() < 0.01);
+    }
+}
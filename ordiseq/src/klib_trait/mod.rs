@@ -1,33 +1,94 @@
+use crate::error::OrdiseqError;
+use klib::core::base::HasName;
 use klib::core::named_pitch::{HasNamedPitch, NamedPitch};
 use klib::core::note::Note;
 use klib::core::octave::{HasOctave, Octave};
 use klib::core::pitch::{HasPitch, Pitch};
 
-pub trait Transposable {
+/// The range of notes that can be both represented as a `Note` and
+/// sent as a MIDI note, in semitones from C0: C0 (MIDI 12) to G9
+/// (MIDI 127).
+const MIDI_NOTE_RANGE: std::ops::RangeInclusive<i32> = 0..=115;
+
+/// What to do with a note transposed outside of the MIDI note range.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OutOfRange {
+    /// Return an error.
+    #[default]
+    Error,
+    /// Use the lowest or highest note of the range instead.
+    Clamp,
+    /// Move the note by octaves to the other end of the range: a note
+    /// too high becomes the lowest note of the range with its pitch.
+    WrapOctave,
+    /// Move the note back by octaves into the range: a note too high
+    /// becomes the highest note of the range with its pitch.
+    FoldOctave,
+}
+
+pub trait Transposable: Sized {
     /// Transposes the note by the given number of semitones.
-    fn transpose(self, semitones: i8) -> Self;
+    ///
+    /// Returns an error if the note falls outside of the MIDI note
+    /// range; use `transpose_with` to keep it within the range instead.
+    fn transpose(self, semitones: i8) -> Result<Self, OrdiseqError> {
+        self.transpose_with(semitones, OutOfRange::Error)
+    }
+
+    /// Transposes the note by the given number of semitones, keeping it
+    /// within the MIDI note range according to the strategy.
+    ///
+    /// Returns an error if the note falls out of range and the strategy
+    /// is `OutOfRange::Error`.
+    fn transpose_with(self, semitones: i8, strategy: OutOfRange) -> Result<Self, OrdiseqError>;
 }
 
 impl Transposable for Note {
-    fn transpose(self, semitones: i8) -> Self {
-        // Get the total semitone offset from the start of the scale.
-        let current_index = self.octave() as i32 * 12 + self.named_pitch().pitch() as i32;
-        let new_index = current_index + semitones as i32;
-
-        // Calculate the new octave and pitch.
-        let new_octave = new_index.div_euclid(12);
-        let new_pitch_index = new_index.rem_euclid(12);
-
-        // Convert back to `Octave` and `NamedPitch`.
-        let new_octave = Octave::try_from(new_octave as u8).expect("Octave out of range");
-        let new_pitch =
-            NamedPitch::from(Pitch::try_from(new_pitch_index as u8).expect("Pitch out of range"));
-
-        // Return the new `Note`.
-        Note::new(new_pitch, new_octave)
+    fn transpose_with(self, semitones: i8, strategy: OutOfRange) -> Result<Self, OrdiseqError> {
+        let new_index = semitones_from_c0(&self) + semitones as i32;
+        let (min, max) = (*MIDI_NOTE_RANGE.start(), *MIDI_NOTE_RANGE.end());
+        let new_index = match strategy {
+            _ if MIDI_NOTE_RANGE.contains(&new_index) => new_index,
+            OutOfRange::Error => {
+                return Err(OrdiseqError::NoteOutOfRange(format!(
+                    "{} transposed by {semitones} semitones",
+                    self.name()
+                )))
+            }
+            OutOfRange::Clamp => new_index.clamp(min, max),
+            // The lowest or highest note of the range with the same pitch:
+            OutOfRange::WrapOctave if new_index > max => min + (new_index - min).rem_euclid(12),
+            OutOfRange::WrapOctave => max - (max - new_index).rem_euclid(12),
+            OutOfRange::FoldOctave if new_index > max => max - (max - new_index).rem_euclid(12),
+            OutOfRange::FoldOctave => min + (new_index - min).rem_euclid(12),
+        };
+        Ok(note_from_semitones(new_index).expect("The MIDI note range fits in the octaves"))
     }
 }
 
+/// Returns whether a number of semitones from C0 is a valid MIDI note.
+pub(crate) fn in_midi_note_range(semitones: i32) -> bool {
+    MIDI_NOTE_RANGE.contains(&semitones)
+}
+
+/// Returns the number of semitones from C0 to the note.
+pub(crate) fn semitones_from_c0(note: &Note) -> i32 {
+    note.octave() as i32 * 12 + note.named_pitch().pitch() as i32
+}
+
+/// Returns the note a number of semitones above C0, or `None` if it is
+/// outside of the octaves of `Octave`.
+pub(crate) fn note_from_semitones(semitones: i32) -> Option<Note> {
+    // Calculate the octave and pitch.
+    let octave = u8::try_from(semitones.div_euclid(12)).ok()?;
+    let pitch_index = semitones.rem_euclid(12);
+
+    // Convert back to `Octave` and `NamedPitch`.
+    let octave = Octave::try_from(octave).ok()?;
+    let pitch = NamedPitch::from(Pitch::try_from(pitch_index as u8).expect("Pitch out of range"));
+    Some(Note::new(pitch, octave))
+}
+
 /// Sequence type for holding a single note or a rest
 #[derive(Clone)]
 pub enum NoteOrRest {
@@ -10,23 +10,24 @@
 //! ## Features
 //!
 //!   * Integrated with the
-//!   [kord](https://crates.io/crates/kord)::[klib](https://docs.rs/kord/latest/klib/)
-//!   crate for music theory, handling
-//!   [`NamedPitches`](crate::prelude::NamedPitch),
-//!   [`Notes`](crate::prelude::Note),
-//!   [`Octaves`](crate::prelude::Octave),
-//!   [`Intervals`](crate::prelude::Interval),
-//!   [`Chords`](crate::prelude::Chord) and more.
+//!     [kord](https://crates.io/crates/kord)::[klib](https://docs.rs/kord/latest/klib/)
+//!     crate for music theory, handling
+//!     [`NamedPitches`](crate::prelude::NamedPitch),
+//!     [`Notes`](crate::prelude::Note),
+//!     [`Octaves`](crate::prelude::Octave),
+//!     [`Intervals`](crate::prelude::Interval),
+//!     [`Chords`](crate::prelude::Chord) and more.
 //!
 //!   * Includes more than 1000 musical
-//!   [`Scales`](crate::prelude::Scale) from [The Scale
-//! Omnibus](https://www.saxopedia.com/the-scale-omnibus)
-//! ([wayback](https://web.archive.org/web/20200220013047/http://www.saxopedia.com/the-scale-omnibus/))
+//!     [`Scales`](crate::prelude::Scale) from [The Scale
+//!     Omnibus](https://www.saxopedia.com/the-scale-omnibus)
+//!     ([wayback](https://web.archive.org/web/20200220013047/http://www.saxopedia.com/the-scale-omnibus/))
 //!
 //! ## Getting started
 //!
 //! ## Examples
 pub mod error;
+pub mod groove;
 pub mod klib_trait;
 pub mod midi;
 pub mod prelude;
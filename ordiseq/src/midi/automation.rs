@@ -0,0 +1,575 @@
+use crate::error::OrdiseqError;
+use crate::midi::Channel;
+use crate::time::Time;
+use midly::{num::u14, MidiMessage, PitchBend, TrackEventKind};
+use std::collections::BTreeMap;
+
+/// The smallest value an exponential curve starts from or goes to, so
+/// that curves from or to zero are still defined: one step of a 14 bit
+/// controller.
+const MIN_EXPONENTIAL_VALUE: f64 = 1.0 / 16383.0;
+
+/// The shape of an automation curve from one breakpoint to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interpolation {
+    /// Holds the value until the next breakpoint.
+    Step,
+    /// Moves to the next value in a straight line.
+    Linear,
+    /// Moves to the next value by a constant ratio per tick: slowly at
+    /// first when rising, quickly at first when falling.
+    Exponential,
+}
+
+/// The channel message an automation lane renders to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AutomationTarget {
+    /// A 7 bit controller (0-119).
+    Controller(u8),
+    /// A 14 bit controller (0-31): the most significant 7 bits on the
+    /// controller, then the least significant 7 bits on the controller
+    /// + 32.
+    Controller14Bit(u8),
+    /// The pitch bend wheel.
+    PitchBend,
+    /// The pressure of the whole channel (channel aftertouch).
+    ChannelPressure,
+    /// The pressure of a single key (polyphonic aftertouch).
+    PolyAftertouch(u8),
+}
+
+impl AutomationTarget {
+    /// Returns the largest raw value of the target.
+    fn max_value(&self) -> u16 {
+        match self {
+            AutomationTarget::Controller14Bit(_) | AutomationTarget::PitchBend => 0x3FFF,
+            _ => 0x7F,
+        }
+    }
+}
+
+/// Represents the automation of one controller, or of the pitch bend
+/// or pressure of a channel: breakpoints holding a value and the curve
+/// leading to the next breakpoint.
+///
+/// Values range from 0 to 1, except for pitch bend which is in
+/// semitones (-128 to 128). Pitch bends are scaled to the pitch bend
+/// range of the channel when rendering, so they do not depend on the
+/// range in force when they are added; bends beyond the range are sent
+/// as full bends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutomationLane {
+    target: AutomationTarget,
+    // Values are stored from 0 to 1, pitch bend included (over the
+    // largest range), so that every curve is interpolated the same way.
+    points: BTreeMap<Time, (f32, Interpolation)>,
+}
+
+impl AutomationLane {
+    /// Creates an empty 7 bit lane for a controller (0-119).
+    pub fn new(controller: u8) -> Result<Self, OrdiseqError> {
+        if controller > 119 {
+            return Err(OrdiseqError::InvalidAutomation(format!(
+                "Controller {controller} is out of range (0-119)"
+            )));
+        }
+        Ok(AutomationLane::for_target(AutomationTarget::Controller(
+            controller,
+        )))
+    }
+
+    /// Creates an empty 14 bit lane for a controller (0-31), paired
+    /// with the controller + 32 for the least significant bits.
+    pub fn new_14bit(controller: u8) -> Result<Self, OrdiseqError> {
+        if controller > 31 {
+            return Err(OrdiseqError::InvalidAutomation(format!(
+                "14 bit controller {controller} is out of range (0-31)"
+            )));
+        }
+        Ok(AutomationLane::for_target(
+            AutomationTarget::Controller14Bit(controller),
+        ))
+    }
+
+    /// Creates an empty pitch bend lane.
+    pub fn pitch_bend() -> Self {
+        AutomationLane::for_target(AutomationTarget::PitchBend)
+    }
+
+    /// Creates an empty channel pressure lane.
+    pub fn channel_pressure() -> Self {
+        AutomationLane::for_target(AutomationTarget::ChannelPressure)
+    }
+
+    /// Creates an empty polyphonic aftertouch lane for a key (0-127).
+    pub fn poly_aftertouch(key: u8) -> Result<Self, OrdiseqError> {
+        if key > 127 {
+            return Err(OrdiseqError::InvalidAutomation(format!(
+                "Key {key} is out of range (0-127)"
+            )));
+        }
+        Ok(AutomationLane::for_target(
+            AutomationTarget::PolyAftertouch(key),
+        ))
+    }
+
+    fn for_target(target: AutomationTarget) -> Self {
+        AutomationLane {
+            target,
+            points: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a breakpoint, replacing any breakpoint at the same time.
+    /// The interpolation is the curve from this breakpoint to the next.
+    pub fn add_point(
+        &mut self,
+        time: Time,
+        value: f32,
+        interpolation: Interpolation,
+    ) -> Result<(), OrdiseqError> {
+        let (min, max) = match self.target {
+            AutomationTarget::PitchBend => (-MAX_PITCH_BEND_RANGE, MAX_PITCH_BEND_RANGE),
+            _ => (0.0, 1.0),
+        };
+        if !(min..=max).contains(&value) {
+            return Err(OrdiseqError::InvalidAutomation(format!(
+                "Value {value} is out of range ({min}-{max})"
+            )));
+        }
+        self.points
+            .insert(time, (self.value_to_unit(value), interpolation));
+        Ok(())
+    }
+
+    /// Removes the breakpoint at a time, returning its value.
+    pub fn remove_point(&mut self, time: Time) -> Option<f32> {
+        self.points
+            .remove(&time)
+            .map(|(value, _)| self.unit_to_value(value))
+    }
+
+    /// Returns the value of the lane at a time, or `None` before the
+    /// first breakpoint.
+    pub fn value_at(&self, time: Time) -> Option<f32> {
+        let (&start, &(from, interpolation)) = self.points.range(..=time).next_back()?;
+        let Some((&end, &(to, _))) = self.points.range(time..).find(|(&end, _)| end > start) else {
+            return Some(self.unit_to_value(from));
+        };
+        let position = (time.ticks - start.ticks) as f64 / (end.ticks - start.ticks) as f64;
+        Some(self.unit_to_value(interpolate(from, to, position, interpolation)))
+    }
+
+    pub fn target(&self) -> AutomationTarget {
+        self.target
+    }
+    /// Returns the breakpoints of the lane in time order.
+    pub fn points(&self) -> impl Iterator<Item = (Time, f32, Interpolation)> + '_ {
+        self.points.iter().map(|(&time, &(value, interpolation))| {
+            (time, self.unit_to_value(value), interpolation)
+        })
+    }
+
+    /// Maps a value of the lane to the 0->1 range it is stored in.
+    fn value_to_unit(&self, value: f32) -> f32 {
+        match self.target {
+            AutomationTarget::PitchBend => (value / MAX_PITCH_BEND_RANGE + 1.0) / 2.0,
+            _ => value,
+        }
+    }
+
+    fn unit_to_value(&self, value: f32) -> f32 {
+        match self.target {
+            AutomationTarget::PitchBend => (value * 2.0 - 1.0) * MAX_PITCH_BEND_RANGE,
+            _ => value,
+        }
+    }
+
+    /// Returns a copy of the lane with every breakpoint moved to the
+    /// matching tick at a different resolution.
+    pub(crate) fn with_resolution(&self, from: u32, to: u32) -> Self {
+        self.retime(|time| Some(time.rescale(from, to)))
+    }
+
+    /// Returns a copy of the lane with every breakpoint moved to a new
+    /// time, dropping the breakpoints moved to `None`.
+    pub(crate) fn retime<F: Fn(Time) -> Option<Time>>(&self, retime: F) -> Self {
+        AutomationLane {
+            points: self
+                .points
+                .iter()
+                .filter_map(|(time, point)| Some((retime(*time)?, *point)))
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Adds a breakpoint at a time holding the value in force there, on
+    /// the same curve, so that the lane keeps its value when it is cut
+    /// at the time. Does nothing before the first breakpoint.
+    pub(crate) fn split_at(&mut self, time: Time) {
+        if self.points.contains_key(&time) {
+            return;
+        }
+        let Some((_, &(_, interpolation))) = self.points.range(..time).next_back() else {
+            return;
+        };
+        if let Some(value) = self.value_at(time) {
+            self.points
+                .insert(time, (self.value_to_unit(value), interpolation));
+        }
+    }
+
+    /// Fills a gap of `length` inserted at a time, after the later
+    /// breakpoints were moved past it: the gap holds the value the lane
+    /// takes up again at its end. Does nothing if the lane starts at or
+    /// after the time.
+    pub(crate) fn hold_gap(&mut self, at: Time, length: Time) {
+        if length.ticks == 0 || self.points.range(..at).next().is_none() {
+            return;
+        }
+        if let Some(&(value, _)) = self.points.get(&(at + length.ticks)) {
+            self.points.insert(at, (value, Interpolation::Step));
+        }
+    }
+
+    /// Adds the breakpoints of another lane of the same target,
+    /// replacing any breakpoint at the same time.
+    pub(crate) fn merge(&mut self, other: &AutomationLane) {
+        self.points.extend(other.points.iter());
+    }
+
+    /// Renders the lane into channel events. Pitch bends are scaled to
+    /// `pitch_bend_range`, in semitones.
+    ///
+    /// Curves are sampled every `density` ticks, and at every
+    /// breakpoint. Samples that do not change the controller value are
+    /// skipped.
+    pub(crate) fn midi_events(
+        &self,
+        channel: Channel,
+        density: Time,
+        pitch_bend_range: f32,
+    ) -> Vec<(u32, TrackEventKind<'static>)> {
+        let density = density.ticks.max(1);
+        let mut events = vec![];
+        let mut last = None;
+        let mut points = self.points.iter().peekable();
+        while let Some((&start, &(from, interpolation))) = points.next() {
+            let mut samples = vec![(start, from)];
+            if let (Some((&end, &(to, _))), false) =
+                (points.peek(), interpolation == Interpolation::Step)
+            {
+                let length = (end.ticks - start.ticks) as f64;
+                samples.extend(
+                    (start.ticks + density..end.ticks)
+                        .step_by(density as usize)
+                        .map(|ticks| {
+                            let position = (ticks - start.ticks) as f64 / length;
+                            (
+                                Time { ticks },
+                                interpolate(from, to, position, interpolation),
+                            )
+                        }),
+                );
+            }
+            for (time, value) in samples {
+                let value = self.to_raw_value(value, pitch_bend_range);
+                if last != Some(value) {
+                    events.extend(self.raw_events(time, channel, value));
+                    last = Some(value);
+                }
+            }
+        }
+        events
+    }
+
+    /// Scales a stored value (0->1) to the raw range of the target.
+    fn to_raw_value(&self, value: f32, pitch_bend_range: f32) -> u16 {
+        let value = match self.target {
+            AutomationTarget::PitchBend => {
+                let bend = (self.unit_to_value(value) / pitch_bend_range).clamp(-1.0, 1.0);
+                (bend + 1.0) / 2.0
+            }
+            _ => value,
+        };
+        (value as f64 * self.target.max_value() as f64).round() as u16
+    }
+
+    fn raw_events(
+        &self,
+        time: Time,
+        channel: Channel,
+        value: u16,
+    ) -> Vec<(u32, TrackEventKind<'static>)> {
+        let controller = |controller: u8, value: u16| MidiMessage::Controller {
+            controller: controller.into(),
+            value: (value as u8).into(),
+        };
+        let messages = match self.target {
+            AutomationTarget::Controller(number) => vec![controller(number, value)],
+            AutomationTarget::Controller14Bit(number) => vec![
+                controller(number, value >> 7),
+                controller(number + 32, value & 0x7F),
+            ],
+            AutomationTarget::PitchBend => vec![MidiMessage::PitchBend {
+                bend: PitchBend(u14::from(value)),
+            }],
+            AutomationTarget::ChannelPressure => vec![MidiMessage::ChannelAftertouch {
+                vel: (value as u8).into(),
+            }],
+            AutomationTarget::PolyAftertouch(key) => vec![MidiMessage::Aftertouch {
+                key: key.into(),
+                vel: (value as u8).into(),
+            }],
+        };
+        messages
+            .into_iter()
+            .map(|message| {
+                (
+                    time.ticks,
+                    TrackEventKind::Midi {
+                        channel: channel.value().into(),
+                        message,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+/// The registered parameter number controllers, and the data entry
+/// controllers setting the value of the selected parameter.
+const RPN_MSB: u8 = 101;
+const RPN_LSB: u8 = 100;
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+
+/// The pitch bend range most instruments default to, in semitones.
+pub const DEFAULT_PITCH_BEND_RANGE: f32 = 2.0;
+
+/// The pitch bend range can be set up to 127 semitones and 99 cents.
+pub(crate) const MAX_PITCH_BEND_RANGE: f32 = 128.0;
+
+/// Checks that a pitch bend at a time, in semitones, is within a pitch
+/// bend range.
+pub(crate) fn check_pitch_bend(time: Time, semitones: f32, range: f32) -> Result<(), OrdiseqError> {
+    if semitones.abs() > range {
+        return Err(OrdiseqError::InvalidAutomation(format!(
+            "Pitch bend of {semitones} semitones at tick {} is beyond the pitch bend range ({range} semitones)",
+            time.ticks
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the events setting the pitch bend range of a channel, in
+/// semitones and cents, through registered parameter 0. The parameter
+/// is deselected afterwards so that later data entry is ignored.
+pub(crate) fn pitch_bend_range_events(
+    ticks: u32,
+    channel: Channel,
+    semitones: f32,
+) -> Vec<(u32, TrackEventKind<'static>)> {
+    let cents = (semitones * 100.0).round() as u32;
+    [
+        (RPN_MSB, 0),
+        (RPN_LSB, 0),
+        (DATA_ENTRY_MSB, (cents / 100) as u8),
+        (DATA_ENTRY_LSB, (cents % 100) as u8),
+        (RPN_MSB, 127),
+        (RPN_LSB, 127),
+    ]
+    .into_iter()
+    .map(|(controller, value)| {
+        (
+            ticks,
+            TrackEventKind::Midi {
+                channel: channel.value().into(),
+                message: MidiMessage::Controller {
+                    controller: controller.into(),
+                    value: value.into(),
+                },
+            },
+        )
+    })
+    .collect()
+}
+
+/// Returns the value at a position (0->1) along a curve.
+fn interpolate(from: f32, to: f32, position: f64, interpolation: Interpolation) -> f32 {
+    let (from, to) = (from as f64, to as f64);
+    let value = match interpolation {
+        Interpolation::Step => from,
+        Interpolation::Linear => from + (to - from) * position,
+        Interpolation::Exponential => {
+            let from = from.max(MIN_EXPONENTIAL_VALUE);
+            let to = to.max(MIN_EXPONENTIAL_VALUE);
+            from * (to / from).powf(position)
+        }
+    };
+    value as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller_values(events: &[(u32, TrackEventKind)]) -> Vec<(u32, u8, u8)> {
+        events
+            .iter()
+            .map(|(ticks, kind)| match kind {
+                TrackEventKind::Midi {
+                    message: MidiMessage::Controller { controller, value },
+                    ..
+                } => (*ticks, controller.as_int(), value.as_int()),
+                _ => panic!("Expected a controller event"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_linear() {
+        let mut lane = AutomationLane::new(7).unwrap();
+        lane.add_point(Time { ticks: 0 }, 0.0, Interpolation::Linear)
+            .unwrap();
+        lane.add_point(Time { ticks: 96 }, 1.0, Interpolation::Step)
+            .unwrap();
+        assert_eq!(lane.value_at(Time { ticks: 48 }), Some(0.5));
+        let events = lane.midi_events(
+            Channel::default(),
+            Time { ticks: 24 },
+            DEFAULT_PITCH_BEND_RANGE,
+        );
+        assert_eq!(
+            controller_values(&events),
+            vec![
+                (0, 7, 0),
+                (24, 7, 32),
+                (48, 7, 64),
+                (72, 7, 95),
+                (96, 7, 127)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        let mut lane = AutomationLane::new(1).unwrap();
+        lane.add_point(Time { ticks: 0 }, 0.5, Interpolation::Step)
+            .unwrap();
+        lane.add_point(Time { ticks: 96 }, 0.5, Interpolation::Step)
+            .unwrap();
+        lane.add_point(Time { ticks: 192 }, 1.0, Interpolation::Step)
+            .unwrap();
+        assert_eq!(lane.value_at(Time { ticks: 191 }), Some(0.5));
+        assert_eq!(lane.value_at(Time { ticks: 500 }), Some(1.0));
+        // The repeated value is not sent again:
+        let events = lane.midi_events(
+            Channel::default(),
+            Time { ticks: 1 },
+            DEFAULT_PITCH_BEND_RANGE,
+        );
+        assert_eq!(controller_values(&events), vec![(0, 1, 64), (192, 1, 127)]);
+    }
+
+    #[test]
+    fn test_exponential() {
+        let mut lane = AutomationLane::new(74).unwrap();
+        lane.add_point(Time { ticks: 0 }, 0.1, Interpolation::Exponential)
+            .unwrap();
+        lane.add_point(Time { ticks: 100 }, 1.0, Interpolation::Step)
+            .unwrap();
+        let middle = lane.value_at(Time { ticks: 50 }).unwrap();
+        assert!((middle - 0.1f32.sqrt()).abs() < 1e-6);
+        assert_eq!(lane.value_at(Time { ticks: 100 }), Some(1.0));
+    }
+
+    #[test]
+    fn test_14bit() {
+        let mut lane = AutomationLane::new_14bit(1).unwrap();
+        lane.add_point(Time { ticks: 0 }, 0.5, Interpolation::Step)
+            .unwrap();
+        let events = lane.midi_events(
+            Channel::default(),
+            Time { ticks: 1 },
+            DEFAULT_PITCH_BEND_RANGE,
+        );
+        // 0.5 * 16383 = 8192 (rounded): MSB 64, LSB 0
+        assert_eq!(controller_values(&events), vec![(0, 1, 64), (0, 33, 0)]);
+        assert!(AutomationLane::new_14bit(32).is_err());
+        assert!(AutomationLane::new(120).is_err());
+        assert!(lane
+            .add_point(Time { ticks: 0 }, 1.5, Interpolation::Step)
+            .is_err());
+    }
+
+    #[test]
+    fn test_pitch_bend() {
+        let mut lane = AutomationLane::pitch_bend();
+        lane.add_point(Time { ticks: 0 }, -2.0, Interpolation::Linear)
+            .unwrap();
+        lane.add_point(Time { ticks: 96 }, 2.0, Interpolation::Step)
+            .unwrap();
+        assert_eq!(lane.value_at(Time { ticks: 48 }), Some(0.0));
+        assert!(lane
+            .add_point(Time { ticks: 0 }, -130.0, Interpolation::Step)
+            .is_err());
+        let bends = |range: f32| -> Vec<(u32, u16)> {
+            lane.midi_events(Channel::default(), Time { ticks: 48 }, range)
+                .into_iter()
+                .map(|(ticks, kind)| match kind {
+                    TrackEventKind::Midi {
+                        message: MidiMessage::PitchBend { bend },
+                        ..
+                    } => (ticks, bend.0.as_int()),
+                    _ => panic!("Expected a pitch bend event"),
+                })
+                .collect()
+        };
+        // Fully down, centered, fully up:
+        assert_eq!(bends(2.0), vec![(0, 0), (48, 8192), (96, 16383)]);
+        // The same semitones over a wider range:
+        assert_eq!(bends(4.0), vec![(0, 4096), (48, 8192), (96, 12287)]);
+        // Bends beyond the range are full bends:
+        assert_eq!(bends(1.0), vec![(0, 0), (48, 8192), (96, 16383)]);
+    }
+
+    #[test]
+    fn test_pressure() {
+        let mut lane = AutomationLane::poly_aftertouch(60).unwrap();
+        lane.add_point(Time { ticks: 0 }, 1.0, Interpolation::Step)
+            .unwrap();
+        assert_eq!(
+            lane.midi_events(
+                Channel::default(),
+                Time { ticks: 1 },
+                DEFAULT_PITCH_BEND_RANGE
+            )[0]
+            .1,
+            TrackEventKind::Midi {
+                channel: 0.into(),
+                message: MidiMessage::Aftertouch {
+                    key: 60.into(),
+                    vel: 127.into()
+                }
+            }
+        );
+        let mut lane = AutomationLane::channel_pressure();
+        lane.add_point(Time { ticks: 0 }, 0.5, Interpolation::Step)
+            .unwrap();
+        assert_eq!(
+            lane.midi_events(
+                Channel::default(),
+                Time { ticks: 1 },
+                DEFAULT_PITCH_BEND_RANGE
+            )[0]
+            .1,
+            TrackEventKind::Midi {
+                channel: 0.into(),
+                message: MidiMessage::ChannelAftertouch { vel: 64.into() }
+            }
+        );
+        assert!(AutomationLane::poly_aftertouch(128).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::OrdiseqError;
+use midly::{MidiMessage, TrackEventKind};
+use std::fmt;
+
+/// The bank select controller, most significant byte.
+const BANK_SELECT_MSB: u8 = 0;
+/// The bank select controller, least significant byte.
+const BANK_SELECT_LSB: u8 = 32;
+
+/// Represents a MIDI channel, numbered from 0 to 15 as in the MIDI
+/// messages themselves. Displayed from 1 to 16, as most instruments
+/// show it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Channel(u8);
+
+impl Channel {
+    /// The General MIDI percussion channel (channel 10).
+    pub const DRUMS: Channel = Channel(9);
+
+    pub fn new(channel: u8) -> Result<Self, OrdiseqError> {
+        if channel > 15 {
+            return Err(OrdiseqError::InvalidChannel(channel));
+        }
+        Ok(Channel(channel))
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0 + 1)
+    }
+}
+
+/// Represents the instrument selected on a channel: a program number,
+/// optionally within a bank.
+///
+/// The bank is a 14 bit number, sent as the bank select MSB (CC 0)
+/// and LSB (CC 32) before the program change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Program {
+    pub program: u8,
+    pub bank: Option<u16>,
+}
+
+impl Program {
+    /// Creates a program change without bank selection.
+    pub fn new(program: u8) -> Result<Self, OrdiseqError> {
+        if program > 127 {
+            return Err(OrdiseqError::InvalidProgram(format!(
+                "Program {program} is out of range (0-127)"
+            )));
+        }
+        Ok(Program {
+            program,
+            bank: None,
+        })
+    }
+
+    /// Selects a bank before the program change.
+    pub fn with_bank(mut self, bank: u16) -> Result<Self, OrdiseqError> {
+        if bank > 0x3FFF {
+            return Err(OrdiseqError::InvalidProgram(format!(
+                "Bank {bank} is out of range (0-16383)"
+            )));
+        }
+        self.bank = Some(bank);
+        Ok(self)
+    }
+
+    /// Returns the bank select and program change events selecting the
+    /// program on a channel at a specific tick.
+    pub(crate) fn midi_events(
+        &self,
+        ticks: u32,
+        channel: Channel,
+    ) -> Vec<(u32, TrackEventKind<'static>)> {
+        let mut messages = vec![];
+        if let Some(bank) = self.bank {
+            messages.push(MidiMessage::Controller {
+                controller: BANK_SELECT_MSB.into(),
+                value: ((bank >> 7) as u8).into(),
+            });
+            messages.push(MidiMessage::Controller {
+                controller: BANK_SELECT_LSB.into(),
+                value: ((bank & 0x7F) as u8).into(),
+            });
+        }
+        messages.push(MidiMessage::ProgramChange {
+            program: self.program.into(),
+        });
+        messages
+            .into_iter()
+            .map(|message| {
+                (
+                    ticks,
+                    TrackEventKind::Midi {
+                        channel: channel.value().into(),
+                        message,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel() {
+        assert_eq!(Channel::new(15).unwrap().value(), 15);
+        assert!(Channel::new(16).is_err());
+        assert_eq!(Channel::DRUMS.to_string(), "10");
+        assert_eq!(Channel::default().value(), 0);
+    }
+
+    #[test]
+    fn test_program_events() {
+        let program = Program::new(5).unwrap().with_bank(300).unwrap();
+        let events = program.midi_events(0, Channel::new(2).unwrap());
+        let messages: Vec<MidiMessage> = events
+            .into_iter()
+            .map(|(_, kind)| match kind {
+                TrackEventKind::Midi { channel, message } => {
+                    assert_eq!(channel.as_int(), 2);
+                    message
+                }
+                _ => panic!("Expected a channel message"),
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                MidiMessage::Controller {
+                    controller: 0.into(),
+                    value: 2.into()
+                },
+                MidiMessage::Controller {
+                    controller: 32.into(),
+                    value: 44.into()
+                },
+                MidiMessage::ProgramChange { program: 5.into() },
+            ]
+        );
+        assert!(Program::new(128).is_err());
+        assert!(Program::new(0).unwrap().with_bank(0x4000).is_err());
+    }
+}
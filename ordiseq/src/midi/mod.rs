@@ -1,14 +1,115 @@
+use crate::error::OrdiseqError;
+use crate::time::{FrameRate, TempoMap, Time};
 use klib::core::{named_pitch::HasNamedPitch, note::Note, octave::HasOctave, pitch::HasPitch};
+use midly::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind};
+
+mod automation;
+mod channel;
+pub use automation::*;
+pub use channel::*;
 
 pub trait HasMidiValue {
+    /// Calculates the MIDI note value, or returns an error if it is
+    /// above 127.
+    fn try_midi_value(&self) -> Result<u8, OrdiseqError>;
+
     /// Calculates the MIDI note value for the current `Note`.
-    fn midi_value(&self) -> u8;
+    ///
+    /// Notes above G9 are not valid MIDI notes and give 127; use
+    /// `try_midi_value` to reject them instead.
+    fn midi_value(&self) -> u8 {
+        self.try_midi_value().unwrap_or(127)
+    }
 }
 
 impl HasMidiValue for Note {
-    fn midi_value(&self) -> u8 {
-        let pitch_offset = self.named_pitch().pitch() as u8;
-        let octave = self.octave() as i8;
-        12 * (octave + 1) as u8 + pitch_offset
+    fn try_midi_value(&self) -> Result<u8, OrdiseqError> {
+        let pitch_offset = self.named_pitch().pitch() as u32;
+        let octave = self.octave() as u32;
+        let value = 12 * (octave + 1) + pitch_offset;
+        if value > 127 {
+            return Err(OrdiseqError::NoteOutOfRange(format!(
+                "MIDI note {value} is above 127"
+            )));
+        }
+        Ok(value as u8)
+    }
+}
+
+/// The largest resolution a MIDI file header can hold (15 bits).
+const MAX_TICKS_PER_QUARTER_NOTE: u32 = 0x7FFF;
+
+/// Checks that a resolution can be written to a MIDI file header.
+pub(crate) fn check_resolution(ticks_per_quarter_note: u32) -> Result<u16, OrdiseqError> {
+    if ticks_per_quarter_note == 0 || ticks_per_quarter_note > MAX_TICKS_PER_QUARTER_NOTE {
+        return Err(OrdiseqError::InvalidResolution(ticks_per_quarter_note));
+    }
+    Ok(ticks_per_quarter_note as u16)
+}
+
+/// Converts events placed at absolute tick positions into a MIDI
+/// track with delta times, ending with an `EndOfTrack` event.
+///
+/// Events at the same tick are ordered: meta events first, then note
+/// offs, then other channel messages, and note ons last. Events of the
+/// same kind keep their original order.
+pub(crate) fn to_track(mut events: Vec<(u32, TrackEventKind<'_>)>) -> Vec<TrackEvent<'_>> {
+    events.sort_by_key(|(ticks, kind)| (*ticks, event_order(kind)));
+
+    let mut track = Vec::with_capacity(events.len() + 1);
+    let mut last_time_ticks = 0;
+    for (time, kind) in events {
+        track.push(TrackEvent {
+            delta: (time - last_time_ticks).into(),
+            kind,
+        });
+        last_time_ticks = time;
+    }
+
+    track.push(TrackEvent {
+        delta: 0.into(),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    track
+}
+
+/// Moves events from tick positions to SMPTE timecode positions,
+/// counted in subdivisions of a frame, following the tempo map.
+pub(crate) fn to_timecode_events<'a>(
+    events: Vec<(u32, TrackEventKind<'a>)>,
+    tempo_map: &TempoMap,
+    frame_rate: FrameRate,
+    ticks_per_frame: u8,
+) -> Result<Vec<(u32, TrackEventKind<'a>)>, OrdiseqError> {
+    if ticks_per_frame == 0 {
+        return Err(OrdiseqError::InvalidTimecode(
+            "A frame needs at least one tick".to_string(),
+        ));
+    }
+    events
+        .into_iter()
+        .map(|(ticks, kind)| {
+            let duration = tempo_map.to_duration(Time { ticks });
+            let count = frame_rate.count(duration, ticks_per_frame as u32);
+            let ticks = u32::try_from(count).map_err(|_| {
+                OrdiseqError::InvalidTimecode(format!(
+                    "Tick {ticks} is too far from the start at {ticks_per_frame} ticks per frame"
+                ))
+            })?;
+            Ok((ticks, kind))
+        })
+        .collect()
+}
+
+fn event_order(kind: &TrackEventKind) -> u8 {
+    match kind {
+        TrackEventKind::Meta(_) => 0,
+        TrackEventKind::Midi { message, .. } => match message {
+            MidiMessage::NoteOff { .. } => 1,
+            MidiMessage::NoteOn { vel, .. } if *vel == 0 => 1,
+            MidiMessage::NoteOn { .. } => 3,
+            _ => 2,
+        },
+        _ => 2,
     }
 }
@@ -6,7 +6,9 @@ pub use std::str::FromStr;
 pub use std::time::Duration;
 
 //Exports from this crate:
+pub use crate::groove::*;
 pub use crate::klib_trait::*;
+pub use crate::midi::{AutomationLane, AutomationTarget, Channel, Interpolation, Program};
 pub use crate::scales::*;
 pub use crate::sequence::*;
 pub use crate::time::*;
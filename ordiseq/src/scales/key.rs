@@ -0,0 +1,229 @@
+use crate::error::OrdiseqError;
+use crate::klib_trait::{in_midi_note_range, note_from_semitones, semitones_from_c0};
+use klib::core::base::{HasName, HasStaticName};
+use klib::core::named_pitch::NamedPitch;
+use klib::core::note::Note;
+use klib::core::pitch::HasPitch;
+use scale_omnibus::Scale;
+use std::fmt;
+
+/// What to do with a note that is not in the key when transposing by
+/// scale degrees.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OutOfScale {
+    /// Move the note to the nearest degree of the scale first (the
+    /// lower one when it is halfway between two degrees).
+    Snap,
+    /// Transpose the degree below the note, and keep the chromatic
+    /// offset from it: a C# in C major, up a step, becomes a D#.
+    #[default]
+    KeepOffset,
+    /// Return an error.
+    Error,
+}
+
+/// Represents a scale starting from a root: "D dorian".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key {
+    root: NamedPitch,
+    scale_name: String,
+    // The degrees of the scale, in semitones above the root (0-11)
+    degrees: Vec<u8>,
+}
+
+impl Key {
+    /// Creates a key from a root and a scale.
+    ///
+    /// The degrees are taken from the notes of the scale, or from its
+    /// intervals. Scales with different ascending and descending forms
+    /// use the ascending form.
+    pub fn new(root: NamedPitch, scale: &Scale) -> Result<Self, OrdiseqError> {
+        let degrees = scale
+            .notes
+            .clone()
+            .or_else(|| scale.notes_ascending.clone())
+            .or_else(|| {
+                let intervals = scale
+                    .intervals
+                    .as_ref()
+                    .or(scale.intervals_ascending.as_ref())?;
+                Some(
+                    intervals
+                        .iter()
+                        .scan(0, |offset, interval| {
+                            let degree = *offset;
+                            *offset += interval;
+                            Some(degree)
+                        })
+                        .collect(),
+                )
+            })
+            .unwrap_or_default();
+        let mut degrees: Vec<u8> = degrees.into_iter().filter(|degree| *degree < 12).collect();
+        degrees.sort_unstable();
+        degrees.dedup();
+        if degrees.first() != Some(&0) {
+            return Err(OrdiseqError::InvalidScale(format!(
+                "{} has no degrees starting from the root",
+                scale.name
+            )));
+        }
+        Ok(Key {
+            root,
+            scale_name: scale.name.clone(),
+            degrees,
+        })
+    }
+
+    /// Creates a key from a root and the name of a scale of the Scale
+    /// Omnibus, such as "Dorian".
+    pub fn from_scale_name(root: NamedPitch, scale_name: &str) -> Result<Self, OrdiseqError> {
+        let scale = scale_omnibus::get_scale(scale_name)
+            .map_err(|error| OrdiseqError::InvalidScale(error.to_string()))?;
+        Key::new(root, scale)
+    }
+
+    pub fn root(&self) -> NamedPitch {
+        self.root
+    }
+    pub fn scale_name(&self) -> &str {
+        &self.scale_name
+    }
+    /// Returns the degrees of the scale, in semitones above the root.
+    pub fn degrees(&self) -> &[u8] {
+        &self.degrees
+    }
+
+    /// Returns whether a note belongs to the key, in any octave.
+    pub fn contains(&self, note: &Note) -> bool {
+        let offset = (semitones_from_c0(note) - self.root.pitch() as i32).rem_euclid(12);
+        self.degrees.contains(&(offset as u8))
+    }
+
+    /// Transposes a note by a number of scale degrees: 2 moves a note
+    /// up a third within the key, -1 moves it down a step.
+    pub fn transpose(
+        &self,
+        note: Note,
+        degrees: i32,
+        policy: OutOfScale,
+    ) -> Result<Note, OrdiseqError> {
+        let semitones = semitones_from_c0(&note);
+        let offset = (semitones - self.root.pitch() as i32).rem_euclid(12);
+        // The root of the key in the octave of the note:
+        let base = semitones - offset;
+
+        let (degree, chromatic) = match self.degrees.iter().position(|d| *d as i32 == offset) {
+            Some(degree) => (degree, 0),
+            None => {
+                // The degree below the note, which the root always is:
+                let below = self
+                    .degrees
+                    .iter()
+                    .rposition(|d| (*d as i32) < offset)
+                    .expect("The scale starts from the root");
+                match policy {
+                    OutOfScale::KeepOffset => (below, offset - self.degrees[below] as i32),
+                    OutOfScale::Snap => {
+                        let above = self.degrees.get(below + 1).map_or(12, |d| *d as i32);
+                        match offset - self.degrees[below] as i32 <= above - offset {
+                            true => (below, 0),
+                            // Past the last degree, snap to the root of
+                            // the next octave:
+                            false => (below + 1, 0),
+                        }
+                    }
+                    OutOfScale::Error => {
+                        return Err(OrdiseqError::NoteOutOfScale(format!(
+                            "{} is not in {self}",
+                            note.name()
+                        )))
+                    }
+                }
+            }
+        };
+
+        let count = self.degrees.len() as i32;
+        let degree = degree as i32 + degrees;
+        let semitones = base
+            + 12 * degree.div_euclid(count)
+            + self.degrees[degree.rem_euclid(count) as usize] as i32
+            + chromatic;
+        match in_midi_note_range(semitones) {
+            true => Ok(
+                note_from_semitones(semitones).expect("The MIDI note range fits in the octaves")
+            ),
+            false => Err(OrdiseqError::NoteOutOfRange(format!(
+                "{} moved by {degrees} degrees in {self}",
+                note.name()
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.root.static_name(), self.scale_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::HasMidiValue;
+    use klib::core::note::{CSharp, C, D, E, F, G};
+
+    fn dorian(root: NamedPitch) -> Key {
+        Key::from_scale_name(root, "dorian").unwrap()
+    }
+
+    #[test]
+    fn test_transpose() {
+        let key = dorian(NamedPitch::D);
+        // Up a third in D dorian: D -> F, E -> G, C -> E
+        let up_a_third = |note: Note| {
+            key.transpose(note, 2, OutOfScale::Error)
+                .unwrap()
+                .midi_value()
+        };
+        assert_eq!(up_a_third(D), F.midi_value());
+        assert_eq!(up_a_third(E), G.midi_value());
+        assert_eq!(up_a_third(C), E.midi_value());
+        // Whole octaves:
+        assert_eq!(
+            key.transpose(D, -7, OutOfScale::Error)
+                .unwrap()
+                .midi_value(),
+            D.midi_value() - 12
+        );
+    }
+
+    #[test]
+    fn test_out_of_scale() {
+        let key = Key::from_scale_name(NamedPitch::C, "major").unwrap();
+        assert!(!key.contains(&CSharp));
+        assert!(key.transpose(CSharp, 1, OutOfScale::Error).is_err());
+        // C# snaps to C, then moves up to D:
+        assert_eq!(
+            key.transpose(CSharp, 1, OutOfScale::Snap)
+                .unwrap()
+                .midi_value(),
+            D.midi_value()
+        );
+        // C# is C + 1, so it becomes D + 1:
+        assert_eq!(
+            key.transpose(CSharp, 1, OutOfScale::KeepOffset)
+                .unwrap()
+                .midi_value(),
+            D.midi_value() + 1
+        );
+    }
+
+    #[test]
+    fn test_key() {
+        let key = dorian(NamedPitch::D);
+        assert_eq!(key.degrees(), &[0, 2, 3, 5, 7, 9, 10]);
+        assert_eq!(key.to_string(), "D Dorian");
+        assert!(Key::from_scale_name(NamedPitch::D, "no such scale").is_err());
+    }
+}
@@ -1,3 +1,6 @@
+mod key;
+pub use key::*;
+
 //use klib::core::chord::Chord;
 //use klib::core::named_pitch::NamedPitch;
 // use serde::Deserialize;
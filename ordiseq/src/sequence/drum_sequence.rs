@@ -1,8 +1,12 @@
-use crate::midi::HasMidiValue;
+use crate::groove::Groove;
+use crate::midi::{check_resolution, to_timecode_events, to_track, Channel, HasMidiValue, Program};
+use crate::sequence::Humanize;
+use crate::time::{Cycle, FrameRate, IntoTicks, MeterMap, Tempo, TempoCurve, TempoMap, Time};
 use crate::{error::OrdiseqError, time::TimeSignature};
+use klib::core::base::HasName;
 use klib::core::note::{HasNoteId, Note};
 use log::info;
-use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
 use std::collections::HashMap;
 
 use super::euclidean_rhythm::generate_euclidean_rhythm;
@@ -10,7 +14,12 @@ use super::euclidean_rhythm::generate_euclidean_rhythm;
 pub struct DrumSequence {
     title: String,                    // Title of the drum sequence
     tracks: HashMap<u128, DrumTrack>, // HashMap keyed by Note ID (u128)
-    time_signature: TimeSignature,
+    meter_map: MeterMap,
+    tempo_map: TempoMap,
+    groove: Option<Groove>,
+    humanize: Option<Humanize>,
+    channel: Channel,
+    program: Option<Program>,
 }
 
 pub struct DrumTrack {
@@ -18,6 +27,8 @@ pub struct DrumTrack {
     title: String, // Title of the track
     rhythm: Vec<(bool, f64)>, // Euclidean rhythm as (hit: bool, velocity: f64)
     rotation: usize,          // Rotation of the rhythm
+    step: Option<Time>,       // Step length, or None for the sequence default
+    channel: Option<Channel>, // MIDI channel, or None for the sequence channel
 }
 
 impl DrumTrack {
@@ -27,17 +38,107 @@ impl DrumTrack {
         rotated.rotate_right(self.rotation);
         rotated
     }
+
+    /// Returns the loop of the track: one step per rhythm step, each
+    /// `default_step` long unless the track sets its own step length.
+    /// Returns `None` for an empty rhythm.
+    pub fn cycle(&self, default_step: Time) -> Option<Cycle> {
+        Cycle::new(self.rhythm.len() as u32, self.step.unwrap_or(default_step)).ok()
+    }
 }
 
 impl DrumSequence {
     pub fn new(title: &str, time_signature: TimeSignature) -> Result<Self, OrdiseqError> {
-        Ok(DrumSequence {
+        check_resolution(time_signature.ticks_per_quarter_note())?;
+        time_signature.check()?;
+        let drum_sequence = DrumSequence {
             title: title.to_string(),
             tracks: HashMap::new(),
-            time_signature,
-        })
+            meter_map: MeterMap::new(time_signature),
+            tempo_map: TempoMap::new(Tempo::default(), time_signature.ticks_per_quarter_note())?,
+            groove: None,
+            humanize: None,
+            channel: Channel::DRUMS,
+            program: None,
+        };
+        drum_sequence.check_cycles()?;
+        Ok(drum_sequence)
+    }
+
+    /// Sets the time signature starting at a specific bar (counting from 1).
+    ///
+    /// Returns an error if a time signature at bar 1 leaves the default
+    /// step shorter than a tick, or the tracks too long to line up.
+    pub fn set_time_signature(
+        &mut self,
+        bar: u32,
+        time_signature: TimeSignature,
+    ) -> Result<(), OrdiseqError> {
+        let previous = self.meter_map.clone();
+        self.meter_map.set_time_signature(bar, time_signature)?;
+        if let Err(error) = self.check_cycles() {
+            self.meter_map = previous;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Sets the length of the pickup (anacrusis) before the first
+    /// downbeat. Bar 1 starts after the pickup.
+    pub fn set_pickup(&mut self, length: Time) -> Result<(), OrdiseqError> {
+        self.meter_map.set_pickup(length)
+    }
+
+    /// Sets the tempo starting at a specific time.
+    pub fn set_tempo(&mut self, time: Time, tempo: Tempo) {
+        self.tempo_map.set_tempo(time, tempo);
+    }
+
+    /// Sets a gradual tempo change between two times.
+    pub fn set_tempo_ramp(
+        &mut self,
+        start: Time,
+        end: Time,
+        from: Tempo,
+        to: Tempo,
+        curve: TempoCurve,
+    ) -> Result<(), OrdiseqError> {
+        self.tempo_map.set_tempo_ramp(start, end, from, to, curve)
+    }
+
+    /// Sets the swing or groove applied when rendering the drum tracks.
+    ///
+    /// Returns an error if the grid of the groove cannot be represented
+    /// at the sequence resolution.
+    pub fn set_groove(&mut self, groove: Groove) -> Result<(), OrdiseqError> {
+        groove.grid.to_ticks(self.ticks_per_quarter_note())?;
+        self.groove = Some(groove);
+        Ok(())
+    }
+
+    /// Removes the swing or groove applied when rendering the drum tracks.
+    pub fn clear_groove(&mut self) -> Option<Groove> {
+        self.groove.take()
+    }
+
+    /// Sets the humanization applied when rendering the drum tracks.
+    ///
+    /// The drum patterns are not changed. Each render starts again from
+    /// the humanize seed, so every render is the same.
+    pub fn set_humanize(&mut self, humanize: Humanize) {
+        self.humanize = Some(humanize);
+    }
+
+    /// Removes the humanization applied when rendering the drum tracks.
+    pub fn clear_humanize(&mut self) -> Option<Humanize> {
+        self.humanize.take()
     }
 
+    /// Adds a track playing a euclidean rhythm on an instrument.
+    ///
+    /// Returns an error if the instrument note is above G9, the highest
+    /// MIDI note, or if the track cannot line up with the other tracks
+    /// within the largest time.
     pub fn add_euclidean_track(
         &mut self,
         title: &str,
@@ -46,105 +147,475 @@ impl DrumSequence {
         pulses: usize,
         rotation: usize,
         velocity: f64,
-    ) {
+    ) -> Result<(), OrdiseqError> {
+        instrument.try_midi_value()?;
         let rhythm = generate_euclidean_rhythm(steps, pulses, velocity);
         info!("{:?}", rhythm);
-        self.tracks.insert(
+        let previous = self.tracks.insert(
             instrument.id(), // Use Note::id() as the key
             DrumTrack {
                 title: title.to_string(),
                 rhythm,
                 rotation,
+                step: None,
+                channel: None,
             },
         );
+        if let Err(error) = self.check_cycles() {
+            match previous {
+                Some(previous) => self.tracks.insert(instrument.id(), previous),
+                None => self.tracks.remove(&instrument.id()),
+            };
+            return Err(error);
+        }
+        Ok(())
     }
 
-    pub fn to_midi(&self) -> Smf {
-        let mut midi_track = vec![];
+    /// Sets the MIDI channel of the drum sequence, used by every track
+    /// that does not set its own channel. Defaults to the General MIDI
+    /// percussion channel (channel 10).
+    pub fn set_channel(&mut self, channel: Channel) {
+        self.channel = channel;
+    }
+
+    /// Sets the program (drum kit) selected at the start of the drum
+    /// sequence, on the sequence channel.
+    pub fn set_program(&mut self, program: Program) {
+        self.program = Some(program);
+    }
+
+    /// Removes the program selected at the start of the drum sequence.
+    pub fn clear_program(&mut self) -> Option<Program> {
+        self.program.take()
+    }
+
+    /// Plays a track on a specific channel instead of the sequence
+    /// channel.
+    pub fn set_track_channel(
+        &mut self,
+        instrument: Note,
+        channel: Channel,
+    ) -> Result<(), OrdiseqError> {
+        let drum_track = self
+            .tracks
+            .get_mut(&instrument.id())
+            .ok_or_else(|| OrdiseqError::UnknownDrumTrack(instrument.name()))?;
+        drum_track.channel = Some(channel);
+        Ok(())
+    }
+
+    /// Sets the step length of a track, so that it loops independently
+    /// of the other tracks.
+    ///
+    /// For example, a 5 step track of quarter notes against a 4 step
+    /// track of quarter notes plays a 5 against 4 polymeter. The
+    /// sequence is rendered until all the tracks line up again.
+    ///
+    /// Returns an error if the tracks would not line up again within the
+    /// largest time.
+    pub fn set_track_step<D: IntoTicks>(
+        &mut self,
+        instrument: Note,
+        step: D,
+    ) -> Result<(), OrdiseqError> {
+        let step = step.into_ticks(self.ticks_per_quarter_note())?;
+        let drum_track = self
+            .tracks
+            .get_mut(&instrument.id())
+            .ok_or_else(|| OrdiseqError::UnknownDrumTrack(instrument.name()))?;
+        let previous = drum_track.step.replace(step);
+        if let Err(error) = self.check_cycles() {
+            if let Some(drum_track) = self.tracks.get_mut(&instrument.id()) {
+                drum_track.step = previous;
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Returns the rendered length of the drum sequence: the least
+    /// common multiple of the cycles of all the tracks.
+    pub fn length(&self) -> Result<Time, OrdiseqError> {
+        let cycles = self.cycles();
+        if cycles.is_empty() {
+            return Ok(Time { ticks: 0 });
+        }
+        Cycle::common_length(&cycles)
+    }
+
+    pub fn to_midi(&self) -> Smf<'_> {
+        Smf {
+            header: midly::Header {
+                format: Format::SingleTrack,
+                timing: Timing::Metrical((self.ticks_per_quarter_note() as u16).into()),
+            },
+            tracks: vec![to_track(self.midi_events())],
+        }
+    }
+
+    /// Exports the drum sequence to a MIDI file timed in SMPTE frames
+    /// instead of ticks.
+    ///
+    /// Returns an error if `ticks_per_frame` is zero, or if an event is
+    /// too far from the start to be counted in ticks.
+    pub fn to_midi_timecode(
+        &self,
+        frame_rate: FrameRate,
+        ticks_per_frame: u8,
+    ) -> Result<Smf<'_>, OrdiseqError> {
+        let events = to_timecode_events(
+            self.midi_events(),
+            &self.tempo_map,
+            frame_rate,
+            ticks_per_frame,
+        )?;
+        Ok(Smf {
+            header: midly::Header {
+                format: Format::SingleTrack,
+                timing: Timing::Timecode(frame_rate.to_midi(), ticks_per_frame),
+            },
+            tracks: vec![to_track(events)],
+        })
+    }
+
+    /// Returns the MIDI events of the drum sequence at absolute tick
+    /// positions.
+    fn midi_events(&self) -> Vec<(u32, TrackEventKind<'_>)> {
+        let mut events = vec![];
 
         // Add metadata (e.g., sequence title)
-        midi_track.push(TrackEvent {
-            delta: 0.into(),
-            kind: TrackEventKind::Meta(MetaMessage::TrackName(self.title.as_bytes())),
-        });
-
-        // Add a time signature event
-        midi_track.push(TrackEvent {
-            delta: 0.into(),
-            kind: TrackEventKind::Meta(MetaMessage::TimeSignature(
-                self.time_signature.beats_per_bar,
-                self.time_signature.beat_unit,
-                24,
-                8,
-            )),
-        });
-
-        // Add each drum track's events
-        for (id, drum_track) in &self.tracks {
-            let instrument = Note::from_id(*id).expect("Invalid Note ID"); // Convert ID back to Note
-            let step_duration = self.time_signature.ticks_per_quarter_note as u32
-                / self.time_signature.beats_per_bar as u32;
+        events.push((
+            0,
+            TrackEventKind::Meta(MetaMessage::TrackName(self.title.as_bytes())),
+        ));
+
+        // Add the time signature events
+        events.extend(self.meter_map.midi_events());
+
+        // Add the tempo events
+        events.extend(self.tempo_map.midi_events());
+
+        events.extend(self.channel_events());
+        events
+    }
+
+    /// Returns the channel events of the drum tracks at absolute tick
+    /// positions, without any meta events: the program selection, then
+    /// the notes.
+    pub(crate) fn channel_events(&self) -> Vec<(u32, TrackEventKind<'static>)> {
+        let mut events = vec![];
+
+        if let Some(program) = &self.program {
+            events.extend(program.midi_events(0, self.channel));
+        }
 
-            let mut previous_time = 0;
+        // Add each drum track's events, in a stable order so that
+        // humanization is reproducible
+        let mut rng = self.humanize.as_ref().map(Humanize::rng);
+        // Every track loops until they all line up again
+        let length = self
+            .length()
+            .expect("The common length of the cycles is checked when they change");
+        let mut ids: Vec<&u128> = self.tracks.keys().collect();
+        ids.sort();
+        for id in ids {
+            let drum_track = &self.tracks[id];
+            let instrument = Note::from_id(*id).expect("Invalid Note ID"); // Convert ID back to Note
+            let Some(cycle) = drum_track.cycle(self.default_step()) else {
+                continue;
+            };
+            let pattern = drum_track.rotated_pattern();
+            let channel = drum_track.channel.unwrap_or(self.channel).value();
 
-            for (step, &(active, velocity)) in drum_track.rotated_pattern().iter().enumerate() {
+            for (step, time) in cycle.step_times(length) {
+                let (active, velocity) = pattern[step as usize];
                 if active {
-                    let current_time = step as u32 * step_duration;
-                    let delta_time = current_time - previous_time;
-                    previous_time = current_time;
+                    let (mut start, mut velocity) = self.apply_groove(time, velocity as f32);
+                    let mut duration = Time {
+                        ticks: cycle.step().ticks / 2,
+                    };
+                    if let (Some(humanize), Some(rng)) = (&self.humanize, &mut rng) {
+                        if !(humanize.preserve_downbeats && self.meter_map.is_downbeat(time)) {
+                            start = humanize.humanize_time(rng, start);
+                            velocity = humanize.humanize_velocity(rng, velocity);
+                            duration = humanize.humanize_duration(rng, duration);
+                        }
+                    }
+                    let current_time = start.ticks;
 
                     let midi_value = instrument.midi_value();
                     let velocity = (velocity * 127.0) as u8;
 
                     // Note On
-                    midi_track.push(TrackEvent {
-                        delta: delta_time.into(),
-                        kind: TrackEventKind::Midi {
-                            channel: 9.into(), // Channel 10 for percussion
+                    events.push((
+                        current_time,
+                        TrackEventKind::Midi {
+                            channel: channel.into(),
                             message: MidiMessage::NoteOn {
                                 key: midi_value.into(),
                                 vel: velocity.into(),
                             },
                         },
-                    });
+                    ));
 
                     // Note Off (short duration)
-                    midi_track.push(TrackEvent {
-                        delta: (step_duration / 2).into(),
-                        kind: TrackEventKind::Midi {
-                            channel: 9.into(),
+                    events.push((
+                        current_time.saturating_add(duration.ticks),
+                        TrackEventKind::Midi {
+                            channel: channel.into(),
                             message: MidiMessage::NoteOff {
                                 key: midi_value.into(),
                                 vel: 0.into(),
                             },
                         },
-                    });
+                    ));
                 }
             }
         }
+        events
+    }
 
-        // End of Track
-        midi_track.push(TrackEvent {
-            delta: 0.into(),
-            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
-        });
+    /// Returns a copy of the drum sequence at a different resolution.
+    ///
+    /// Returns an error if the default step would be shorter than a tick
+    /// at the new resolution, or the tracks too long to line up.
+    pub fn with_resolution(mut self, ticks_per_quarter_note: u32) -> Result<Self, OrdiseqError> {
+        check_resolution(ticks_per_quarter_note)?;
+        if let Some(groove) = &self.groove {
+            groove.grid.to_ticks(ticks_per_quarter_note)?;
+        }
+        let from = self.ticks_per_quarter_note();
+        self.meter_map = self.meter_map.with_resolution(ticks_per_quarter_note)?;
+        for drum_track in self.tracks.values_mut() {
+            drum_track.step = drum_track.step.map(|step| Time {
+                ticks: step.rescale(from, ticks_per_quarter_note).ticks.max(1),
+            });
+        }
+        self.tempo_map = self.tempo_map.with_resolution(ticks_per_quarter_note)?;
+        self.check_cycles()?;
+        Ok(self)
+    }
 
-        // Return the literal Smf
-        Smf {
-            header: midly::Header {
-                format: Format::SingleTrack,
-                timing: Timing::Metrical(
-                    (self.time_signature.ticks_per_quarter_note as u16).into(),
-                ),
-            },
-            tracks: vec![midi_track],
+    /// Returns the start time and velocity of a hit after applying the
+    /// groove of the drum sequence.
+    fn apply_groove(&self, time: Time, velocity: f32) -> (Time, f32) {
+        match &self.groove {
+            Some(groove) => groove
+                .apply(time, velocity, self.ticks_per_quarter_note())
+                .expect("The groove grid is checked against the sequence resolution"),
+            None => (time, velocity),
+        }
+    }
+
+    /// Returns the step length of tracks that do not set their own:
+    /// a quarter note divided by the beats per bar of the first bar.
+    fn default_step(&self) -> Time {
+        let time_signature = self.time_signature();
+        Time {
+            ticks: time_signature.ticks_per_quarter_note() / time_signature.beats_per_bar() as u32,
+        }
+    }
+
+    /// Checks that the default step is at least a tick long, that every
+    /// track with a rhythm has a cycle, and that the cycles line up again
+    /// within the largest time.
+    fn check_cycles(&self) -> Result<(), OrdiseqError> {
+        let default_step = self.default_step();
+        if default_step.ticks == 0 {
+            return Err(OrdiseqError::InvalidCycle(format!(
+                "The default step of {} is shorter than a tick at {} ticks per quarter note",
+                self.time_signature(),
+                self.ticks_per_quarter_note()
+            )));
         }
+        for drum_track in self.tracks.values() {
+            if !drum_track.rhythm.is_empty() {
+                Cycle::new(
+                    drum_track.rhythm.len() as u32,
+                    drum_track.step.unwrap_or(default_step),
+                )?;
+            }
+        }
+        self.length()?;
+        Ok(())
+    }
+
+    fn cycles(&self) -> Vec<Cycle> {
+        self.tracks
+            .values()
+            .filter_map(|drum_track| drum_track.cycle(self.default_step()))
+            .collect()
+    }
+
+    /// Returns the resolution of the drum sequence, used for the MIDI
+    /// file header.
+    pub fn ticks_per_quarter_note(&self) -> u32 {
+        self.meter_map.ticks_per_quarter_note()
     }
 
     pub fn title(&self) -> String {
         self.title.clone()
     }
+    /// Returns the time signature of the first bar.
     pub fn time_signature(&self) -> TimeSignature {
-        self.time_signature
+        self.meter_map.time_signature_at_bar(1)
+    }
+    /// Returns the time signature in force at a specific time.
+    pub fn time_signature_at(&self, time: Time) -> TimeSignature {
+        self.meter_map.time_signature_at(time)
+    }
+    pub fn meter_map(&self) -> &MeterMap {
+        &self.meter_map
+    }
+    pub fn groove(&self) -> Option<&Groove> {
+        self.groove.as_ref()
+    }
+    pub fn humanize(&self) -> Option<&Humanize> {
+        self.humanize.as_ref()
+    }
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+    pub fn program(&self) -> Option<&Program> {
+        self.program.as_ref()
+    }
+    pub fn tempo_map(&self) -> &TempoMap {
+        &self.tempo_map
+    }
+    pub fn tempo_map_mut(&mut self) -> &mut TempoMap {
+        &mut self.tempo_map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::common_time;
+    use klib::core::note::{C, D};
+
+    fn drums() -> DrumSequence {
+        let mut seq = DrumSequence::new("drums", common_time()).unwrap();
+        seq.add_euclidean_track("Kick", C, 8, 3, 0, 0.8).unwrap();
+        seq.add_euclidean_track("Snare", D, 8, 2, 2, 0.6).unwrap();
+        seq
+    }
+
+    #[test]
+    fn test_humanize_is_reproducible() {
+        let plain = drums();
+        let mut a = drums();
+        let mut b = drums();
+        let humanize = Humanize::new(7).timing(Time { ticks: 3 }).velocity(0.1);
+        a.set_humanize(humanize.clone());
+        b.set_humanize(humanize);
+        assert_eq!(a.to_midi(), b.to_midi());
+        assert_ne!(a.to_midi(), plain.to_midi());
+    }
+
+    fn note_on_times(seq: &DrumSequence, key: u8) -> Vec<u32> {
+        seq.midi_events()
+            .into_iter()
+            .filter_map(|(ticks, kind)| match kind {
+                TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { key: k, .. },
+                    ..
+                } if k == key => Some(ticks),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_polymeter() {
+        let mut seq = DrumSequence::new("drums", common_time()).unwrap();
+        seq.add_euclidean_track("Kick", C, 5, 1, 0, 0.8).unwrap();
+        seq.add_euclidean_track("Snare", D, 4, 1, 0, 0.6).unwrap();
+        seq.set_track_step(C, 1).unwrap();
+        seq.set_track_step(D, 1).unwrap();
+        assert_eq!(seq.length().unwrap(), Time { ticks: 20 * 96 });
+
+        let kick = C.midi_value();
+        let snare = D.midi_value();
+        assert_eq!(
+            note_on_times(&seq, kick),
+            vec![0, 480, 960, 1440],
+            "5 beat cycle"
+        );
+        assert_eq!(
+            note_on_times(&seq, snare),
+            vec![0, 384, 768, 1152, 1536],
+            "4 beat cycle"
+        );
+    }
+
+    #[test]
+    fn test_default_step() {
+        // Without a step, tracks use the default step and loop together:
+        let seq = drums();
+        assert_eq!(seq.length().unwrap(), Time { ticks: 8 * 24 });
+        assert!(DrumSequence::new("drums", common_time())
+            .unwrap()
+            .set_track_step(C, 1)
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_track_step() {
+        let mut seq = drums();
+        assert!(seq.set_track_step(C, Time { ticks: 0 }).is_err());
+        assert!(seq.set_track_step(C, Time { ticks: u32::MAX }).is_err());
+        // The track is still rendered with the default step:
+        assert_eq!(seq.length().unwrap(), Time { ticks: 8 * 24 });
+        // Cycles that would not line up within the largest time:
+        seq.set_track_step(C, Time { ticks: 65_537 }).unwrap();
+        assert!(seq.set_track_step(D, Time { ticks: 65_539 }).is_err());
+        assert_eq!(
+            seq.length().unwrap(),
+            Time {
+                ticks: 8 * 65_537 * 24
+            }
+        );
+    }
+
+    #[test]
+    fn test_default_step_shorter_than_a_tick() {
+        // A quarter note of 2 ticks cannot be split into 4 steps:
+        let time_signature = TimeSignature::new("4/4", 2).unwrap();
+        assert!(DrumSequence::new("drums", time_signature).is_err());
+        let mut seq = DrumSequence::new("drums", TimeSignature::new("2/4", 2).unwrap()).unwrap();
+        assert!(seq.set_time_signature(1, time_signature).is_err());
+        assert_eq!(seq.time_signature().beats_per_bar(), 2);
+        assert!(drums().with_resolution(2).is_err());
+    }
+
+    #[test]
+    fn test_channels() {
+        let mut seq = drums();
+        seq.set_program(Program::new(25).unwrap());
+        seq.set_track_channel(D, Channel::new(1).unwrap()).unwrap();
+        assert!(seq
+            .set_track_channel(klib::core::note::E, Channel::new(1).unwrap())
+            .is_err());
+        let events = seq.channel_events();
+        assert!(matches!(
+            events[0],
+            (
+                0,
+                TrackEventKind::Midi {
+                    message: MidiMessage::ProgramChange { .. },
+                    ..
+                }
+            )
+        ));
+        for (_, kind) in &events {
+            if let TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn { key, .. },
+            } = kind
+            {
+                let expected = if *key == C.midi_value() { 9 } else { 1 };
+                assert_eq!(channel.as_int(), expected);
+            }
+        }
     }
 }
@@ -13,9 +13,9 @@ pub fn generate_euclidean_rhythm(steps: usize, pulses: usize, velocity: f64) ->
 
     // Create rhythm using Euclidean distribution
     let mut rhythm = vec![(false, 0.0); steps];
-    for i in 0..steps {
+    for (i, step) in rhythm.iter_mut().enumerate() {
         if (i * pulses) % steps < pulses {
-            rhythm[i] = (true, velocity); // Add a hit
+            *step = (true, velocity); // Add a hit
         }
     }
 
@@ -0,0 +1,152 @@
+use crate::time::{Time, TimeDelta};
+use crate::util::random::SeededRng;
+
+/// The distribution of random humanization offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    /// Every offset within the bounds is equally likely.
+    Uniform,
+    /// Offsets cluster around zero, with a standard deviation of a
+    /// third of the bounds. Offsets are clamped to the bounds.
+    Gaussian,
+}
+
+/// Options for randomly varying the timing, velocity and duration of
+/// notes.
+///
+/// The same seed always produces the same result, so renders and test
+/// fixtures stay deterministic.
+///
+/// ```rust
+/// use ordiseq::prelude::*;
+///
+/// let humanize = Humanize::new(1234)
+///     .timing(Time { ticks: 4 })
+///     .velocity(0.05)
+///     .distribution(Distribution::Gaussian);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Humanize {
+    /// Maximum timing offset, earlier or later.
+    pub timing: Time,
+    /// Maximum velocity offset, on the 0->1 velocity scale.
+    pub velocity: f32,
+    /// Maximum duration change, as a fraction of the note duration.
+    pub duration: f32,
+    pub distribution: Distribution,
+    pub seed: u64,
+    /// Leave notes that start on the first beat of a bar untouched.
+    pub preserve_downbeats: bool,
+}
+
+impl Humanize {
+    /// Creates humanize options that change nothing until the bounds
+    /// are set.
+    pub fn new(seed: u64) -> Self {
+        Humanize {
+            timing: Time { ticks: 0 },
+            velocity: 0.0,
+            duration: 0.0,
+            distribution: Distribution::Uniform,
+            seed,
+            preserve_downbeats: false,
+        }
+    }
+
+    pub fn timing(mut self, timing: Time) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    pub fn velocity(mut self, velocity: f32) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn duration(mut self, duration: f32) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn distribution(mut self, distribution: Distribution) -> Self {
+        self.distribution = distribution;
+        self
+    }
+
+    pub fn preserve_downbeats(mut self, preserve_downbeats: bool) -> Self {
+        self.preserve_downbeats = preserve_downbeats;
+        self
+    }
+
+    /// Creates a new random number generator from the seed.
+    pub(crate) fn rng(&self) -> SeededRng {
+        SeededRng::new(self.seed)
+    }
+
+    /// Returns a randomly moved start time.
+    pub(crate) fn humanize_time(&self, rng: &mut SeededRng, time: Time) -> Time {
+        let offset = (self.sample(rng) * self.timing.ticks as f64).round() as i64;
+        time.saturating_add(TimeDelta::new(offset))
+    }
+
+    /// Returns a randomly varied velocity, within 0->1.
+    pub(crate) fn humanize_velocity(&self, rng: &mut SeededRng, velocity: f32) -> f32 {
+        (velocity + self.sample(rng) as f32 * self.velocity).clamp(0.0, 1.0)
+    }
+
+    /// Returns a randomly varied duration of at least one tick.
+    pub(crate) fn humanize_duration(&self, rng: &mut SeededRng, duration: Time) -> Time {
+        let scale = 1.0 + self.sample(rng) * self.duration as f64;
+        Time {
+            ticks: ((duration.ticks as f64 * scale).round() as u32).max(1),
+        }
+    }
+
+    /// Returns a random number in the range [-1, 1].
+    fn sample(&self, rng: &mut SeededRng) -> f64 {
+        match self.distribution {
+            Distribution::Uniform => rng.uniform(),
+            Distribution::Gaussian => (rng.gaussian() / 3.0).clamp(-1.0, 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounds() {
+        for distribution in [Distribution::Uniform, Distribution::Gaussian] {
+            let humanize = Humanize::new(99)
+                .timing(Time { ticks: 10 })
+                .velocity(0.1)
+                .duration(0.5)
+                .distribution(distribution);
+            let mut rng = humanize.rng();
+            for _ in 0..1000 {
+                let time = humanize.humanize_time(&mut rng, Time { ticks: 100 });
+                assert!((90..=110).contains(&time.ticks));
+                let velocity = humanize.humanize_velocity(&mut rng, 0.5);
+                assert!((0.4..=0.6).contains(&velocity));
+                let duration = humanize.humanize_duration(&mut rng, Time { ticks: 96 });
+                assert!((48..=144).contains(&duration.ticks));
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_bounds_changes_nothing() {
+        let humanize = Humanize::new(5);
+        let mut rng = humanize.rng();
+        assert_eq!(
+            humanize.humanize_time(&mut rng, Time { ticks: 7 }),
+            Time { ticks: 7 }
+        );
+        assert_eq!(humanize.humanize_velocity(&mut rng, 0.3), 0.3);
+        assert_eq!(
+            humanize.humanize_duration(&mut rng, Time { ticks: 9 }),
+            Time { ticks: 9 }
+        );
+    }
+}
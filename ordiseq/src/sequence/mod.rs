@@ -1,5 +1,12 @@
 mod drum_sequence;
 mod euclidean_rhythm;
+mod humanize;
+mod quantize;
+#[allow(clippy::module_inception)]
 mod sequence;
+mod song;
 pub use drum_sequence::*;
+pub use humanize::*;
+pub use quantize::*;
 pub use sequence::*;
+pub use song::*;
@@ -0,0 +1,179 @@
+use crate::error::OrdiseqError;
+use crate::groove::Groove;
+use crate::time::{NoteValue, Time};
+
+/// Options for quantizing the notes of a sequence to a grid.
+///
+/// Created with `Quantize::new` and adjusted with its builder methods:
+///
+/// ```rust
+/// use ordiseq::prelude::*;
+///
+/// let quantize = Quantize::new(NoteValue::new(Division::Sixteenth))
+///     .strength(0.75)
+///     .window(0.25);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantize {
+    /// The grid to move notes to.
+    pub grid: NoteValue,
+    /// How far notes move towards the grid, from 0 (not at all) to 1
+    /// (all the way).
+    pub strength: f32,
+    /// Only notes closer to the grid than this fraction of a grid step
+    /// are moved. `None` moves every note.
+    pub window: Option<f32>,
+    /// A swing or groove that moves the grid targets.
+    pub swing: Option<Groove>,
+    /// Also quantize the ends of notes. Ignored if `preserve_length`
+    /// is set.
+    pub ends: bool,
+    /// Keep the length of every note, moving its end with its start.
+    pub preserve_length: bool,
+}
+
+impl Quantize {
+    /// Creates quantize options that move note starts all the way to
+    /// the grid, preserving note lengths.
+    pub fn new(grid: NoteValue) -> Self {
+        Quantize {
+            grid,
+            strength: 1.0,
+            window: None,
+            swing: None,
+            ends: false,
+            preserve_length: true,
+        }
+    }
+
+    pub fn strength(mut self, strength: f32) -> Self {
+        self.strength = strength.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn window(mut self, window: f32) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    pub fn swing(mut self, swing: Groove) -> Self {
+        self.swing = Some(swing);
+        self
+    }
+
+    pub fn ends(mut self, ends: bool) -> Self {
+        self.ends = ends;
+        self
+    }
+
+    pub fn preserve_length(mut self, preserve_length: bool) -> Self {
+        self.preserve_length = preserve_length;
+        self
+    }
+
+    /// Returns the length of one grid step in ticks.
+    pub fn step(&self, ticks_per_quarter_note: u32) -> Result<Time, OrdiseqError> {
+        self.grid.to_ticks(ticks_per_quarter_note)
+    }
+
+    /// Returns the quantized position of a single time.
+    ///
+    /// The target is the nearest of the grid steps around the time,
+    /// after they are moved by the swing.
+    pub fn quantize_time(
+        &self,
+        time: Time,
+        ticks_per_quarter_note: u32,
+    ) -> Result<Time, OrdiseqError> {
+        let step = self.step(ticks_per_quarter_note)?.ticks;
+        let before = time.ticks as u64 / step as u64 * step as u64;
+        let mut target: Option<Time> = None;
+        for ticks in [before, before + step as u64] {
+            let Ok(ticks) = u32::try_from(ticks) else {
+                continue;
+            };
+            let mut candidate = Time { ticks };
+            if let Some(swing) = &self.swing {
+                candidate = swing.apply(candidate, 0.0, ticks_per_quarter_note)?.0;
+            }
+            // Halfway between two steps, the later one wins
+            if target.is_none_or(|target| (candidate - time).abs() <= (target - time).abs()) {
+                target = Some(candidate);
+            }
+        }
+        let target = target.expect("The grid step before the time is a valid time");
+
+        let distance = target - time;
+        if let Some(window) = self.window {
+            if distance.abs().ticks as f32 > window * step as f32 {
+                return Ok(time);
+            }
+        }
+        Ok(time + distance * self.strength)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groove::SwingGrid;
+    use crate::time::Division;
+
+    fn sixteenths() -> Quantize {
+        Quantize::new(NoteValue::new(Division::Sixteenth))
+    }
+
+    fn quantize(quantize: &Quantize, ticks: u32) -> u32 {
+        quantize.quantize_time(Time { ticks }, 96).unwrap().ticks
+    }
+
+    #[test]
+    fn test_full_strength() {
+        let q = sixteenths();
+        assert_eq!(quantize(&q, 0), 0);
+        assert_eq!(quantize(&q, 11), 0);
+        assert_eq!(quantize(&q, 12), 24);
+        assert_eq!(quantize(&q, 30), 24);
+    }
+
+    #[test]
+    fn test_strength() {
+        let q = sixteenths().strength(0.5);
+        assert_eq!(quantize(&q, 10), 5);
+        assert_eq!(quantize(&q, 40), 44);
+    }
+
+    #[test]
+    fn test_window() {
+        let q = sixteenths().window(0.25);
+        assert_eq!(quantize(&q, 6), 0);
+        assert_eq!(quantize(&q, 7), 7);
+        assert_eq!(quantize(&q, 42), 48);
+    }
+
+    #[test]
+    fn test_swing_targets() {
+        let q = Quantize::new(NoteValue::new(Division::Eighth))
+            .swing(Groove::swing(75.0, SwingGrid::Eighth).unwrap());
+        assert_eq!(quantize(&q, 5), 0);
+        assert_eq!(quantize(&q, 50), 72);
+        assert_eq!(quantize(&q, 70), 72);
+        // Notes go to the nearest swung step, not to the swung nearest
+        // straight step:
+        assert_eq!(quantize(&q, 30), 0);
+        assert_eq!(quantize(&q, 80), 72);
+        assert_eq!(quantize(&q, 90), 96);
+    }
+
+    #[test]
+    fn test_largest_time() {
+        let q = sixteenths();
+        assert_eq!(quantize(&q, u32::MAX - 5), u32::MAX - 15);
+    }
+
+    #[test]
+    fn test_inexact_grid() {
+        let q = Quantize::new(NoteValue::new(Division::SixtyFourth).tuplet(5, 4));
+        assert!(q.quantize_time(Time { ticks: 10 }, 96).is_err());
+    }
+}
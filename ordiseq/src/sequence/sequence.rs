@@ -3,19 +3,51 @@
 //! Represent a musical sequence of notes and chords.
 //! Includes function to export a sequence to a MIDI file.
 
-use crate::klib_trait::{IntoNoteOrRest, NoteOrRest, Transposable};
-use crate::time::{Time, TimeSignature};
-use crate::{error::OrdiseqError, midi::HasMidiValue, time::calculate_tpqn};
+use crate::error::OrdiseqError;
+use crate::groove::Groove;
+use crate::klib_trait::{IntoNoteOrRest, NoteOrRest, OutOfRange, Transposable};
+use crate::midi::{
+    check_pitch_bend, check_resolution, pitch_bend_range_events, to_timecode_events, to_track,
+    AutomationLane, AutomationTarget, Channel, HasMidiValue, Interpolation, Program,
+    DEFAULT_PITCH_BEND_RANGE, MAX_PITCH_BEND_RANGE,
+};
+use crate::scales::{Key, OutOfScale};
+use crate::sequence::{Humanize, Quantize};
+use crate::time::{
+    Cycle, FrameRate, IntoTicks, IntoTime, MeterMap, Position, Tempo, TempoCurve, TempoMap, Time,
+    TimeDelta, TimeSignature,
+};
 use klib::core::note::Note;
-use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use midly::{Format, MidiMessage, Smf, Timing, TrackEventKind};
+use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
+use std::ops::{Add, BitOr, Bound, Range, RangeBounds};
 
 /// Represents a single note in the sequence.
 #[derive(Debug, Clone, PartialEq)]
-struct SequenceNote {
-    pub note: Note,
-    pub velocity: f32, // 0->1
-    pub duration: Time,
+pub struct SequenceNote {
+    note: Note,
+    velocity: f32, // 0->1
+    duration: Time,
+    channel: Option<Channel>, // None for the sequence channel
+}
+
+impl SequenceNote {
+    pub fn note(&self) -> Note {
+        self.note
+    }
+    /// Returns the velocity of the note, from 0 to 1.
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+    pub fn duration(&self) -> Time {
+        self.duration
+    }
+    /// Returns the channel of the note, or `None` if it plays on the
+    /// sequence channel.
+    pub fn channel(&self) -> Option<Channel> {
+        self.channel
+    }
 }
 
 /// Represents a chord of notes in the sequence.
@@ -23,48 +55,461 @@ struct SequenceNote {
 /// All notes in a chord have the same start time, but each may have a
 /// different duration.
 #[derive(Debug, Clone, PartialEq)]
-struct SequenceChord {
-    pub sequence_notes: Vec<SequenceNote>,
+pub struct SequenceChord {
+    sequence_notes: Vec<SequenceNote>,
+}
+
+impl SequenceChord {
+    pub fn notes(&self) -> &[SequenceNote] {
+        &self.sequence_notes
+    }
 }
 
 /// Represents an element of the sequence: a note, a chord
 #[derive(Debug, Clone, PartialEq)]
-enum SequenceElement {
+pub enum SequenceElement {
     Note(SequenceNote),
     Chord(SequenceChord),
 }
 
+impl SequenceElement {
+    /// Returns the notes of the element: a single note, or the notes of
+    /// a chord.
+    pub fn notes(&self) -> &[SequenceNote] {
+        match self {
+            SequenceElement::Note(sequence_note) => std::slice::from_ref(sequence_note),
+            SequenceElement::Chord(chord) => chord.sequence_notes.as_slice(),
+        }
+    }
+
+    fn notes_mut(&mut self) -> &mut [SequenceNote] {
+        match self {
+            SequenceElement::Note(sequence_note) => std::slice::from_mut(sequence_note),
+            SequenceElement::Chord(chord) => chord.sequence_notes.as_mut_slice(),
+        }
+    }
+}
+
+/// A note that a transposition moved outside of the MIDI note range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutOfRangeNote {
+    /// The start time of the note.
+    pub time: Time,
+    /// The note before the transposition.
+    pub note: Note,
+}
+
+/// Checks that a range to edit does not end before it starts.
+fn check_edit_range(range: &Range<Time>) -> Result<(), OrdiseqError> {
+    if range.end < range.start {
+        return Err(OrdiseqError::InvalidEdit(format!(
+            "The range ends at tick {} before it starts at tick {}",
+            range.end.ticks, range.start.ticks
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the bounds of a range of times in a form `BTreeMap::range`
+/// accepts, or `None` if the range ends before it starts. A range that
+/// excludes both ends at the same time is empty.
+fn ordered_range<R: RangeBounds<Time>>(range: &R) -> Option<(Bound<Time>, Bound<Time>)> {
+    match (range.start_bound().cloned(), range.end_bound().cloned()) {
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) if end < start => None,
+        (Bound::Excluded(start), Bound::Excluded(end)) if start == end => {
+            Some((Bound::Included(start), Bound::Excluded(end)))
+        }
+        bounds => Some(bounds),
+    }
+}
+
+/// Selects one voice of the notes starting at the same time, by pitch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Voice {
+    /// The lowest note (the bass).
+    Lowest,
+    /// The highest note (the melody, or top voice).
+    Highest,
+    /// The nth note counting up from the lowest, starting at 0.
+    FromBottom(usize),
+}
+
+impl Voice {
+    /// Returns the index of the voice within the pitches of the notes
+    /// starting at the same time, or `None` if there are fewer voices.
+    /// Notes of the same pitch are counted in the order they were added.
+    fn index(&self, pitches: &[u8]) -> Option<usize> {
+        let mut order: Vec<usize> = (0..pitches.len()).collect();
+        order.sort_by_key(|&index| pitches[index]);
+        match self {
+            Voice::Lowest => order.first().copied(),
+            Voice::Highest => order.last().copied(),
+            Voice::FromBottom(voice) => order.get(*voice).copied(),
+        }
+    }
+}
+
+/// Where a sequence starts when it is appended to another.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Alignment {
+    /// Right when the last note of the first sequence ends.
+    #[default]
+    EndTime,
+    /// On the first bar line at or after the end of the first
+    /// sequence.
+    NextBar,
+}
+
 /// Represents a sequence of elements placed at specific times.
+///
+/// Any number of elements may start at the same time. They are kept in
+/// the order they were added.
 #[derive(Debug, Clone)]
 pub struct Sequence {
     title: String,
-    time_signature: TimeSignature,
-    elements: BTreeMap<Time, SequenceElement>,
+    meter_map: MeterMap,
+    tempo_map: TempoMap,
+    groove: Option<Groove>,
+    humanize: Option<Humanize>,
+    channel: Channel,
+    // Programs, lanes and pitch bend ranges by channel, None for the
+    // sequence channel. Channels without a range use the instrument
+    // default.
+    programs: BTreeMap<Option<Channel>, Program>,
+    automation: BTreeMap<(Option<Channel>, AutomationTarget), AutomationLane>,
+    automation_density: Time,
+    pitch_bend_ranges: BTreeMap<Option<Channel>, f32>, // Semitones
+    elements: BTreeMap<Time, Vec<SequenceElement>>,
 }
 
 impl Sequence {
-    /// Creates a new empty sequence with the given time signature and PPQ.
+    /// Creates a new empty sequence with the given time signature.
+    ///
+    /// The resolution of the sequence is the ticks per quarter note of
+    /// the time signature.
     pub fn new(title: &str, time_signature: TimeSignature) -> Result<Self, OrdiseqError> {
+        check_resolution(time_signature.ticks_per_quarter_note())?;
+        time_signature.check()?;
         Ok(Self {
             title: title.to_string(),
-            time_signature,
+            meter_map: MeterMap::new(time_signature),
+            tempo_map: TempoMap::new(Tempo::default(), time_signature.ticks_per_quarter_note())?,
+            groove: None,
+            humanize: None,
+            channel: Channel::default(),
+            programs: BTreeMap::new(),
+            automation: BTreeMap::new(),
+            automation_density: Time {
+                ticks: (time_signature.ticks_per_quarter_note() / 8).max(1),
+            },
+            pitch_bend_ranges: BTreeMap::new(),
             elements: BTreeMap::new(),
         })
     }
 
-    /// Adds a note to the sequence at a specific time.
-    pub fn add_note<N>(&mut self, time: Time, note: N, velocity: f32, duration: Time)
+    /// Sets the time signature starting at a specific bar (counting from 1).
+    pub fn set_time_signature(
+        &mut self,
+        bar: u32,
+        time_signature: TimeSignature,
+    ) -> Result<(), OrdiseqError> {
+        self.meter_map.set_time_signature(bar, time_signature)
+    }
+
+    /// Sets the length of the pickup (anacrusis) before the first
+    /// downbeat. Bar 1 starts after the pickup.
+    pub fn set_pickup(&mut self, length: Time) -> Result<(), OrdiseqError> {
+        self.meter_map.set_pickup(length)
+    }
+
+    /// Sets the tempo starting at a specific time.
+    pub fn set_tempo(&mut self, time: Time, tempo: Tempo) {
+        self.tempo_map.set_tempo(time, tempo);
+    }
+
+    /// Sets a gradual tempo change between two times.
+    pub fn set_tempo_ramp(
+        &mut self,
+        start: Time,
+        end: Time,
+        from: Tempo,
+        to: Tempo,
+        curve: TempoCurve,
+    ) -> Result<(), OrdiseqError> {
+        self.tempo_map.set_tempo_ramp(start, end, from, to, curve)
+    }
+
+    /// Sets the swing or groove applied when rendering the sequence.
+    ///
+    /// The stored notes are not changed. Returns an error if the grid
+    /// of the groove cannot be represented at the sequence resolution.
+    pub fn set_groove(&mut self, groove: Groove) -> Result<(), OrdiseqError> {
+        groove.grid.to_ticks(self.ticks_per_quarter_note())?;
+        self.groove = Some(groove);
+        Ok(())
+    }
+
+    /// Removes the swing or groove applied when rendering the sequence.
+    pub fn clear_groove(&mut self) -> Option<Groove> {
+        self.groove.take()
+    }
+
+    /// Sets the humanization applied when rendering the sequence.
+    ///
+    /// The stored notes are not changed. Each render starts again from
+    /// the humanize seed, so every render is the same.
+    pub fn set_humanize(&mut self, humanize: Humanize) {
+        self.humanize = Some(humanize);
+    }
+
+    /// Removes the humanization applied when rendering the sequence.
+    pub fn clear_humanize(&mut self) -> Option<Humanize> {
+        self.humanize.take()
+    }
+
+    /// Sets the MIDI channel of the sequence, used by every note that
+    /// does not set its own channel. Defaults to the first channel.
+    pub fn set_channel(&mut self, channel: Channel) {
+        self.channel = channel;
+    }
+
+    /// Sets the program (and bank) selected at the start of the
+    /// sequence, on the sequence channel.
+    pub fn set_program(&mut self, program: Program) {
+        self.programs.insert(None, program);
+    }
+
+    /// Removes the program selected at the start of the sequence, on
+    /// the sequence channel.
+    pub fn clear_program(&mut self) -> Option<Program> {
+        self.programs.remove(&None)
+    }
+
+    /// Adds an automation breakpoint for a controller at a specific
+    /// time or `Position`, creating a 7 bit lane for the controller if
+    /// there is none yet.
+    ///
+    /// The interpolation is the curve from this breakpoint to the next.
+    pub fn add_automation_point<T: IntoTime>(
+        &mut self,
+        controller: u8,
+        time: T,
+        value: f32,
+        interpolation: Interpolation,
+    ) -> Result<(), OrdiseqError> {
+        let time = time.into_time(&self.meter_map)?;
+        self.add_lane_point(
+            AutomationTarget::Controller(controller),
+            || AutomationLane::new(controller),
+            time,
+            value,
+            interpolation,
+        )
+    }
+
+    /// Sets the pitch bend range of the sequence channel, in semitones.
+    /// The range is sent at the start of the sequence.
+    ///
+    /// Pitch bends are kept in semitones and scaled to the range when
+    /// rendering, so the range can be changed after adding them. The
+    /// range cannot be narrowed below a pitch bend already added.
+    ///
+    /// The range is sent to the nearest cent, so it must round to
+    /// between 1 cent and 127 semitones and 99 cents.
+    pub fn set_pitch_bend_range(&mut self, semitones: f32) -> Result<(), OrdiseqError> {
+        let cents = (semitones * 100.0).round();
+        if !(cents >= 1.0 && cents < MAX_PITCH_BEND_RANGE * 100.0) {
+            return Err(OrdiseqError::InvalidAutomation(format!(
+                "Pitch bend range {semitones} is out of range (0.01 to 127.99 semitones)"
+            )));
+        }
+        if let Some(lane) = self.automation(AutomationTarget::PitchBend) {
+            for (time, bend, _) in lane.points() {
+                check_pitch_bend(time, bend, semitones)?;
+            }
+        }
+        self.pitch_bend_ranges.insert(None, semitones);
+        Ok(())
+    }
+
+    /// Adds a pitch bend, in semitones, at a specific time or
+    /// `Position`. With `Interpolation::Step` the bend is a single
+    /// event; otherwise it bends smoothly to the next pitch bend.
+    pub fn add_pitch_bend<T: IntoTime>(
+        &mut self,
+        time: T,
+        semitones: f32,
+        interpolation: Interpolation,
+    ) -> Result<(), OrdiseqError> {
+        let time = time.into_time(&self.meter_map)?;
+        check_pitch_bend(
+            time,
+            semitones,
+            self.pitch_bend_range().unwrap_or(DEFAULT_PITCH_BEND_RANGE),
+        )?;
+        self.add_lane_point(
+            AutomationTarget::PitchBend,
+            || Ok(AutomationLane::pitch_bend()),
+            time,
+            semitones,
+            interpolation,
+        )
+    }
+
+    /// Adds a channel pressure value (0->1) at a specific time or
+    /// `Position`.
+    pub fn add_channel_pressure<T: IntoTime>(
+        &mut self,
+        time: T,
+        value: f32,
+        interpolation: Interpolation,
+    ) -> Result<(), OrdiseqError> {
+        let time = time.into_time(&self.meter_map)?;
+        self.add_lane_point(
+            AutomationTarget::ChannelPressure,
+            || Ok(AutomationLane::channel_pressure()),
+            time,
+            value,
+            interpolation,
+        )
+    }
+
+    /// Adds a polyphonic aftertouch value (0->1) for a single note at a
+    /// specific time or `Position`.
+    pub fn add_aftertouch<T: IntoTime>(
+        &mut self,
+        time: T,
+        note: Note,
+        value: f32,
+        interpolation: Interpolation,
+    ) -> Result<(), OrdiseqError> {
+        let time = time.into_time(&self.meter_map)?;
+        let key = note.midi_value();
+        self.add_lane_point(
+            AutomationTarget::PolyAftertouch(key),
+            || AutomationLane::poly_aftertouch(key),
+            time,
+            value,
+            interpolation,
+        )
+    }
+
+    /// Adds a breakpoint to the lane of a target, creating the lane if
+    /// there is none yet.
+    fn add_lane_point<F>(
+        &mut self,
+        target: AutomationTarget,
+        new_lane: F,
+        time: Time,
+        value: f32,
+        interpolation: Interpolation,
+    ) -> Result<(), OrdiseqError>
+    where
+        F: FnOnce() -> Result<AutomationLane, OrdiseqError>,
+    {
+        let lane = match self.automation.entry((None, target)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(new_lane()?),
+        };
+        lane.add_point(time, value, interpolation)
+    }
+
+    /// Sets an automation lane, replacing any lane already set for the
+    /// same target. Use this to add 14 bit controller lanes.
+    pub fn set_automation(&mut self, lane: AutomationLane) {
+        self.automation.insert((None, lane.target()), lane);
+    }
+
+    /// Removes the automation lane of a target.
+    pub fn remove_automation(&mut self, target: AutomationTarget) -> Option<AutomationLane> {
+        self.automation.remove(&(None, target))
+    }
+
+    /// Removes the automation lane of a target on a channel, or on the
+    /// sequence channel for `None`. Lanes on other channels come from
+    /// sequences joined with `concat` or `overlay`.
+    pub fn remove_automation_on_channel(
+        &mut self,
+        channel: Option<Channel>,
+        target: AutomationTarget,
+    ) -> Option<AutomationLane> {
+        self.automation.remove(&(channel, target))
+    }
+
+    /// Sets how often automation curves are sampled when rendering:
+    /// a number of quarter notes, a `NoteValue` or a `Time`.
+    pub fn set_automation_density<D: IntoTicks>(&mut self, density: D) -> Result<(), OrdiseqError> {
+        let density = density.into_ticks(self.ticks_per_quarter_note())?;
+        if density.ticks == 0 {
+            return Err(OrdiseqError::InvalidAutomation(
+                "The density must be at least one tick".to_string(),
+            ));
+        }
+        self.automation_density = density;
+        Ok(())
+    }
+
+    /// Adds a note to the sequence at a specific time or `Position`.
+    ///
+    /// Elements already starting at the same time are kept. Returns an
+    /// error if the note is above G9, the highest MIDI note.
+    pub fn add_note<T, N>(
+        &mut self,
+        time: T,
+        note: N,
+        velocity: f32,
+        duration: Time,
+    ) -> Result<(), OrdiseqError>
+    where
+        T: IntoTime,
+        N: IntoNoteOrRest,
+    {
+        self.add_note_with_channel(time, note, velocity, duration, None)
+    }
+
+    /// Adds a note played on a specific channel instead of the sequence
+    /// channel.
+    pub fn add_note_on_channel<T, N>(
+        &mut self,
+        time: T,
+        note: N,
+        velocity: f32,
+        duration: Time,
+        channel: Channel,
+    ) -> Result<(), OrdiseqError>
+    where
+        T: IntoTime,
+        N: IntoNoteOrRest,
+    {
+        self.add_note_with_channel(time, note, velocity, duration, Some(channel))
+    }
+
+    fn add_note_with_channel<T, N>(
+        &mut self,
+        time: T,
+        note: N,
+        velocity: f32,
+        duration: Time,
+        channel: Option<Channel>,
+    ) -> Result<(), OrdiseqError>
     where
+        T: IntoTime,
         N: IntoNoteOrRest,
     {
+        let time = time.into_time(&self.meter_map)?;
         match note.into_note_or_rest() {
             NoteOrRest::Note(note) => {
-                self.elements.insert(
+                note.try_midi_value()?;
+                self.insert(
                     time,
                     SequenceElement::Note(SequenceNote {
                         note,
                         velocity,
                         duration,
+                        channel,
                     }),
                 );
             }
@@ -72,184 +517,2100 @@ impl Sequence {
                 // Ignore explicit rests, they are implied by the notes.
             }
         }
+        Ok(())
     }
 
-    /// Adds a chord to the sequence at a specific time.
-    pub fn add_chord(
+    /// Adds a chord to the sequence at a specific time or `Position`.
+    ///
+    /// Elements already starting at the same time are kept. Returns an
+    /// error if any note is above G9, the highest MIDI note.
+    pub fn add_chord<T>(
         &mut self,
-        time: Time,
+        time: T,
         notes: Vec<(Note, f32, Time)>, // Vec of (Note, velocity, duration)
-    ) {
+    ) -> Result<(), OrdiseqError>
+    where
+        T: IntoTime,
+    {
+        self.add_chord_with_channel(time, notes, None)
+    }
+
+    /// Adds a chord played on a specific channel instead of the
+    /// sequence channel.
+    pub fn add_chord_on_channel<T>(
+        &mut self,
+        time: T,
+        notes: Vec<(Note, f32, Time)>,
+        channel: Channel,
+    ) -> Result<(), OrdiseqError>
+    where
+        T: IntoTime,
+    {
+        self.add_chord_with_channel(time, notes, Some(channel))
+    }
+
+    fn add_chord_with_channel<T>(
+        &mut self,
+        time: T,
+        notes: Vec<(Note, f32, Time)>,
+        channel: Option<Channel>,
+    ) -> Result<(), OrdiseqError>
+    where
+        T: IntoTime,
+    {
+        let time = time.into_time(&self.meter_map)?;
+        for (note, _, _) in &notes {
+            note.try_midi_value()?;
+        }
         let sequence_notes = notes
             .into_iter()
             .map(|(note, velocity, duration)| SequenceNote {
                 note,
                 velocity,
                 duration,
+                channel,
             })
             .collect();
         let chord = SequenceChord { sequence_notes };
-        self.elements.insert(time, SequenceElement::Chord(chord));
+        self.insert(time, SequenceElement::Chord(chord));
+        Ok(())
+    }
+
+    /// Replaces all the elements starting at a time with a single note.
+    ///
+    /// Returns an error if the note is above G9, leaving the elements
+    /// in place.
+    pub fn replace_note<T, N>(
+        &mut self,
+        time: T,
+        note: N,
+        velocity: f32,
+        duration: Time,
+    ) -> Result<(), OrdiseqError>
+    where
+        T: IntoTime,
+        N: IntoNoteOrRest,
+    {
+        let time = time.into_time(&self.meter_map)?;
+        let note = note.into_note_or_rest();
+        if let NoteOrRest::Note(note) = &note {
+            note.try_midi_value()?;
+        }
+        self.remove_at(time)?;
+        self.add_note(time, note, velocity, duration)
+    }
+
+    /// Replaces all the elements starting at a time with a single chord.
+    ///
+    /// Returns an error if any note is above G9, leaving the elements
+    /// in place.
+    pub fn replace_chord<T>(
+        &mut self,
+        time: T,
+        notes: Vec<(Note, f32, Time)>,
+    ) -> Result<(), OrdiseqError>
+    where
+        T: IntoTime,
+    {
+        let time = time.into_time(&self.meter_map)?;
+        for (note, _, _) in &notes {
+            note.try_midi_value()?;
+        }
+        self.remove_at(time)?;
+        self.add_chord(time, notes)
+    }
+
+    /// Removes all the elements starting at a time, returning how many
+    /// were removed.
+    pub fn remove_at<T: IntoTime>(&mut self, time: T) -> Result<usize, OrdiseqError> {
+        let time = time.into_time(&self.meter_map)?;
+        Ok(self
+            .elements
+            .remove(&time)
+            .map_or(0, |elements| elements.len()))
     }
 
+    /// Adds an element after any elements already at the same time.
+    fn insert(&mut self, time: Time, element: SequenceElement) {
+        self.elements.entry(time).or_default().push(element);
+    }
+
+    /// Returns the sequence transposed by a number of semitones, notes
+    /// and chords alike.
+    ///
+    /// Returns an error listing every note that would fall outside of
+    /// the MIDI note range.
     pub fn transpose(mut self, semitones: i8) -> Result<Self, OrdiseqError> {
-        for (_time, element) in &mut self.elements {
-            match element {
-                SequenceElement::Note(note) => {
-                    note.note = note.note.transpose(semitones);
+        self.transpose_in_place(semitones)?;
+        Ok(self)
+    }
+
+    /// Transposes every note and chord of the sequence in place. On
+    /// error the sequence is left unchanged.
+    pub fn transpose_in_place(&mut self, semitones: i8) -> Result<(), OrdiseqError> {
+        self.transpose_range(.., semitones)
+    }
+
+    /// Transposes the notes and chords starting within a range of
+    /// times, such as `Time { ticks: 384 }..Time { ticks: 768 }`.
+    pub fn transpose_range<R: RangeBounds<Time>>(
+        &mut self,
+        range: R,
+        semitones: i8,
+    ) -> Result<(), OrdiseqError> {
+        self.transpose_with(range, None, semitones, OutOfRange::Error)
+            .map(|_| ())
+    }
+
+    /// Transposes a single voice of the notes and chords starting within
+    /// a range of times, for example only the bass voice. Use `..` for
+    /// the whole sequence.
+    ///
+    /// Voices are counted across every note and chord starting at the
+    /// same time, so single notes added at the same time are voices just
+    /// like the notes of a chord.
+    pub fn transpose_voice<R: RangeBounds<Time>>(
+        &mut self,
+        range: R,
+        voice: Voice,
+        semitones: i8,
+    ) -> Result<(), OrdiseqError> {
+        self.transpose_with(range, Some(voice), semitones, OutOfRange::Error)
+            .map(|_| ())
+    }
+
+    /// Transposes the notes and chords starting within a range of
+    /// times, or a single voice of them, keeping every note within the
+    /// MIDI note range according to the strategy.
+    ///
+    /// Returns the notes that fell out of range, as they were before
+    /// the transposition. With `OutOfRange::Error` they are returned in
+    /// an `OrdiseqError::NotesOutOfRange` error instead, and the
+    /// sequence is left unchanged. Returns an `OrdiseqError::InvalidEdit`
+    /// error if the range ends before it starts.
+    pub fn transpose_with<R: RangeBounds<Time>>(
+        &mut self,
+        range: R,
+        voice: Option<Voice>,
+        semitones: i8,
+        strategy: OutOfRange,
+    ) -> Result<Vec<OutOfRangeNote>, OrdiseqError> {
+        let range = ordered_range(&range).ok_or_else(|| {
+            OrdiseqError::InvalidEdit("The range of times ends before it starts".to_string())
+        })?;
+        let mut elements = self.elements.clone();
+        let mut out_of_range = vec![];
+        for (&time, elements) in elements.range_mut(range) {
+            // The (element, note) position of every note starting at the
+            // time, in the order they were added
+            let positions: Vec<(usize, usize)> = elements
+                .iter()
+                .enumerate()
+                .flat_map(|(index, element)| {
+                    (0..element.notes().len()).map(move |note| (index, note))
+                })
+                .collect();
+            let indexes: Vec<usize> = match voice {
+                Some(voice) => {
+                    let pitches: Vec<u8> = positions
+                        .iter()
+                        .map(|&(element, note)| elements[element].notes()[note].note.midi_value())
+                        .collect();
+                    voice.index(&pitches).into_iter().collect()
+                }
+                None => (0..positions.len()).collect(),
+            };
+            for index in indexes {
+                let (element, note) = positions[index];
+                let sequence_note = &mut elements[element].notes_mut()[note];
+                let note = sequence_note.note;
+                if note.transpose_with(semitones, OutOfRange::Error).is_err() {
+                    out_of_range.push(OutOfRangeNote { time, note });
                 }
-                SequenceElement::Chord(_) => {
-                    return Err(OrdiseqError::ChordTranspositionUnsupported);
+                if let Ok(note) = note.transpose_with(semitones, strategy) {
+                    sequence_note.note = note;
                 }
             }
         }
-        Ok(self)
+        if strategy == OutOfRange::Error && !out_of_range.is_empty() {
+            return Err(OrdiseqError::NotesOutOfRange(out_of_range));
+        }
+        self.elements = elements;
+        Ok(out_of_range)
+    }
+
+    /// Transposes every note and chord by a number of scale degrees
+    /// within a key: 2 moves the sequence up a third, keeping every
+    /// note in the mode.
+    ///
+    /// Notes outside of the key follow the `OutOfScale` policy. On
+    /// error the sequence is left unchanged.
+    pub fn transpose_diatonic(
+        &mut self,
+        key: &Key,
+        degrees: i32,
+        policy: OutOfScale,
+    ) -> Result<(), OrdiseqError> {
+        let mut elements = self.elements.clone();
+        for element in elements.values_mut().flatten() {
+            for sequence_note in element.notes_mut() {
+                sequence_note.note = key.transpose(sequence_note.note, degrees, policy)?;
+            }
+        }
+        self.elements = elements;
+        Ok(())
     }
 
-    pub fn load<N>(
+    /// Loads a list of notes one after another, starting at the
+    /// beginning of the sequence.
+    ///
+    /// Each entry is (note or rest, duration, velocity, release
+    /// scale). The duration is either a number of quarter notes, a
+    /// `NoteValue` or a `Time`. Returns an error at the first note
+    /// above G9, the highest MIDI note.
+    pub fn load<N, D>(
         &mut self,
-        notes: &Vec<(N, u32, f32, f32)>,
+        notes: &Vec<(N, D, f32, f32)>,
     ) -> Result<(), Box<dyn std::error::Error>>
     where
         N: IntoNoteOrRest + Clone,
+        D: IntoTicks + Copy,
     {
         let mut start_time = Time { ticks: 0 };
+        let ticks_per_quarter_note = self.time_signature().ticks_per_quarter_note();
 
         for (note, duration, velocity, release_scale) in notes {
-            let length = self.time_signature().beat_time(*duration as f32);
+            let length = duration.into_ticks(ticks_per_quarter_note)?;
             let end_time = Time {
                 ticks: start_time.ticks + length.ticks,
             };
 
             // Add the note while scaling the duration according to release_scale:
-            self.add_note(start_time, note.clone(), *velocity, length * *release_scale);
+            self.add_note(start_time, note.clone(), *velocity, length * *release_scale)?;
             start_time = end_time;
         }
 
         Ok(())
     }
 
-    /// Converts the sequence into a MIDI `Smf` (Standard MIDI File).
-    pub fn to_midi(&self) -> Smf {
-        let mut track = Vec::new();
-
-        // Add a time signature event
-        track.push(TrackEvent {
-            delta: 0.into(),
-            kind: TrackEventKind::Meta(MetaMessage::TimeSignature(
-                self.time_signature.beats_per_bar,
-                self.time_signature.beat_unit,
-                24,
-                8,
-            )),
-        });
-
-        // Collect all note-on and note-off events
-        let mut events = Vec::new();
+    /// Loops the first cycle of the sequence until `length`.
+    ///
+    /// Elements starting within the first repetition of the cycle are
+    /// copied into every later repetition, and elements starting after
+    /// it are replaced. Use `Cycle::common_length` to find the length at
+    /// which several looping sequences line up again.
+    pub fn repeat_cycle(&mut self, cycle: Cycle, length: Time) {
+        let cycle_length = cycle.length();
+        self.elements.split_off(&cycle_length);
+        let pattern: Vec<(Time, Vec<SequenceElement>)> = self
+            .elements
+            .iter()
+            .map(|(time, elements)| (*time, elements.clone()))
+            .collect();
 
-        for (&time, element) in &self.elements {
-            match element {
-                SequenceElement::Note(sequence_note) => {
-                    // Note On
-                    events.push((
-                        time.ticks,
-                        TrackEventKind::Midi {
-                            channel: 0.into(),
-                            message: MidiMessage::NoteOn {
-                                key: sequence_note.note.midi_value().into(),
-                                vel: ((sequence_note.velocity * 127.0).round() as u8).into(),
-                            },
-                        },
-                    ));
-
-                    // Note Off
-                    let end_time_ticks = time.ticks + sequence_note.duration.ticks;
-                    events.push((
-                        end_time_ticks,
-                        TrackEventKind::Midi {
-                            channel: 0.into(),
-                            message: MidiMessage::NoteOff {
-                                key: sequence_note.note.midi_value().into(),
-                                vel: 0.into(),
-                            },
-                        },
-                    ));
+        let mut offset = cycle_length.ticks as u64;
+        while offset < length.ticks as u64 {
+            for (time, elements) in &pattern {
+                let ticks = offset + time.ticks as u64;
+                if ticks >= length.ticks as u64 {
+                    break;
                 }
-                SequenceElement::Chord(chord) => {
-                    for sequence_note in &chord.sequence_notes {
-                        // Note On
-                        events.push((
-                            time.ticks,
-                            TrackEventKind::Midi {
-                                channel: 0.into(),
-                                message: MidiMessage::NoteOn {
-                                    key: sequence_note.note.midi_value().into(),
-                                    vel: ((sequence_note.velocity * 127.0).round() as u8).into(),
-                                },
-                            },
-                        ));
-                    }
+                self.elements.insert(
+                    Time {
+                        ticks: ticks as u32,
+                    },
+                    elements.clone(),
+                );
+            }
+            offset += cycle_length.ticks as u64;
+        }
+    }
 
-                    // Note Offs
-                    for sequence_note in &chord.sequence_notes {
-                        let end_time_ticks = time.ticks + sequence_note.duration.ticks;
-                        events.push((
-                            end_time_ticks,
-                            TrackEventKind::Midi {
-                                channel: 0.into(),
-                                message: MidiMessage::NoteOff {
-                                    key: sequence_note.note.midi_value().into(),
-                                    vel: 0.into(),
-                                },
+    /// Splits every note that starts before a time and ends after it
+    /// into two notes meeting at that time.
+    ///
+    /// The second halves of the notes of an element are added at the
+    /// cut as a new element: a single note, or a chord of the notes
+    /// that crossed the cut.
+    pub fn split_at(&mut self, time: Time) {
+        let mut tails: Vec<SequenceElement> = vec![];
+        for (start, elements) in self.elements.range_mut(..time) {
+            for element in elements {
+                let mut notes = vec![];
+                for sequence_note in element.notes_mut() {
+                    let end = start.ticks as u64 + sequence_note.duration.ticks as u64;
+                    if end > time.ticks as u64 {
+                        let head = time.ticks - start.ticks;
+                        notes.push(SequenceNote {
+                            duration: Time {
+                                ticks: (end - time.ticks as u64) as u32,
                             },
-                        ));
+                            ..sequence_note.clone()
+                        });
+                        sequence_note.duration = Time { ticks: head };
                     }
                 }
+                match notes.len() {
+                    0 => {}
+                    1 => tails.push(SequenceElement::Note(notes.remove(0))),
+                    _ => tails.push(SequenceElement::Chord(SequenceChord {
+                        sequence_notes: notes,
+                    })),
+                }
             }
         }
+        for tail in tails {
+            self.insert(time, tail);
+        }
+    }
 
-        // Sort events by time ticks
-        events.sort_by_key(|&(ticks, _)| ticks);
+    /// Keeps only the part of the sequence within a range of times.
+    ///
+    /// Notes crossing the ends of the range are cut at them. Times are
+    /// not changed: use `shift` to move the result to the start.
+    /// Automation breakpoints outside of the range are removed, and
+    /// breakpoints are added at the ends of the range holding the
+    /// values in force there.
+    pub fn crop(&mut self, range: Range<Time>) -> Result<(), OrdiseqError> {
+        check_edit_range(&range)?;
+        self.split_at(range.start);
+        self.split_at(range.end);
+        self.split_automation_at(range.start);
+        self.split_automation_at(range.end);
+        // The automation keeps its breakpoint at the end of the range,
+        // so that the curve leading to it is unchanged
+        self.retime(|time| (range.start..=range.end).contains(&time).then_some(time));
+        self.elements.remove(&range.end);
+        Ok(())
+    }
 
-        // Add sorted events to the track
-        let mut last_time_ticks = 0;
+    /// Inserts silence at a time, moving every later element and
+    /// automation breakpoint later by `length`. Notes crossing the time
+    /// are split, and the automation holds the value in force at the
+    /// time through the silence.
+    pub fn insert_time(&mut self, at: Time, length: Time) -> Result<(), OrdiseqError> {
+        self.check_end(length)?;
+        self.split_at(at);
+        // The curves carry on from their value at `at` after the silence
+        self.split_automation_at(at);
+        self.retime(|time| match time < at {
+            true => Some(time),
+            false => Some(time + length.ticks),
+        });
+        for lane in self.automation.values_mut() {
+            lane.hold_gap(at, length);
+        }
+        Ok(())
+    }
 
-        for (time, kind) in events {
-            if time < last_time_ticks {
-                panic!(
-                    "Time ticks are not in increasing order: {} < {}",
-                    time, last_time_ticks
-                );
+    /// Deletes a range of times, moving every later element and
+    /// automation breakpoint earlier to close the gap. Notes crossing
+    /// the ends of the range are split, and their parts within the
+    /// range are deleted. The automation takes the value in force at
+    /// the end of the range from the closed gap on.
+    pub fn delete_range(&mut self, range: Range<Time>) -> Result<(), OrdiseqError> {
+        check_edit_range(&range)?;
+        self.split_at(range.start);
+        self.split_at(range.end);
+        // The curves are kept up to the last tick before the range, and
+        // the value in force at the end of the range carries on from the
+        // closed gap. The breakpoint at the start of the range is
+        // replaced by the one moved from its end.
+        if range.start < range.end && range.start.ticks > 0 {
+            self.split_automation_at(Time {
+                ticks: range.start.ticks - 1,
+            });
+        }
+        self.split_automation_at(range.start);
+        self.split_automation_at(range.end);
+        let length = range.end.ticks - range.start.ticks;
+        self.retime(|time| match time {
+            _ if time < range.start => Some(time),
+            _ if time < range.end => None,
+            _ => Some(time - length),
+        });
+        Ok(())
+    }
+
+    /// Moves every element and automation breakpoint by a delta, which
+    /// may be negative.
+    ///
+    /// Returns an error, leaving the sequence unchanged, if anything
+    /// would move before the start of the sequence or past the largest
+    /// time.
+    pub fn shift(&mut self, delta: TimeDelta) -> Result<(), OrdiseqError> {
+        let first = self.elements.keys().next().copied();
+        let first_point = self
+            .automation
+            .values()
+            .filter_map(|lane| lane.points().next().map(|(time, _, _)| time))
+            .min();
+        for time in first.into_iter().chain(first_point) {
+            if time.checked_add(delta).is_none() {
+                return Err(OrdiseqError::InvalidEdit(format!(
+                    "Shifting by {delta} moves tick {} before the start",
+                    time.ticks
+                )));
             }
+        }
+        self.check_end(Time {
+            ticks: delta.ticks.max(0) as u32,
+        })?;
+        self.retime(|time| Some(time + delta));
+        Ok(())
+    }
 
-            let delta = (time - last_time_ticks).into();
-            track.push(TrackEvent { delta, kind });
-            last_time_ticks = time;
+    /// Checks that every element and automation breakpoint can move
+    /// later by `length`, with the notes still ending within the
+    /// largest time.
+    fn check_end(&self, length: Time) -> Result<(), OrdiseqError> {
+        let last_point = self
+            .automation
+            .values()
+            .filter_map(|lane| lane.points().last().map(|(time, _, _)| time))
+            .max()
+            .unwrap_or(Time { ticks: 0 });
+        let end = self.end_time().max(last_point);
+        if end.checked_add_ticks(length.ticks).is_none() {
+            return Err(OrdiseqError::InvalidEdit(format!(
+                "Moving later by {} ticks goes past the largest time",
+                length.ticks
+            )));
         }
+        Ok(())
+    }
 
-        // End of track event
-        track.push(TrackEvent {
-            delta: 0.into(),
-            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
-        });
+    /// Adds a breakpoint to every automation lane at a time, holding
+    /// the value in force there.
+    fn split_automation_at(&mut self, time: Time) {
+        for lane in self.automation.values_mut() {
+            lane.split_at(time);
+        }
+    }
+
+    /// Moves every element and automation breakpoint to a new time,
+    /// removing the ones moved to `None`. Elements moved to the same
+    /// time keep their order.
+    fn retime<F: Fn(Time) -> Option<Time>>(&mut self, retime: F) {
+        let mut elements: BTreeMap<Time, Vec<SequenceElement>> = BTreeMap::new();
+        for (time, element) in self.elements() {
+            if let Some(time) = retime(time) {
+                elements.entry(time).or_default().push(element.clone());
+            }
+        }
+        self.elements = elements;
+        for lane in self.automation.values_mut() {
+            *lane = lane.retime(&retime);
+        }
+    }
+
+    /// Returns this sequence followed by another one, converted to the
+    /// resolution of this sequence if needed.
+    ///
+    /// The notes and automation of `other` are moved to start at the
+    /// end of this sequence. They keep sounding on its channel, with
+    /// its program and pitch bend range. On the same channel, the
+    /// program of this sequence is kept. The title, tempo, meter,
+    /// groove and humanization of this sequence are kept.
+    ///
+    /// Returns an error if the sequences set different pitch bend
+    /// ranges on the same channel, or if a pitch bend ends up beyond
+    /// the range of its channel. Neither sequence is changed.
+    pub fn concat(&self, other: &Sequence, alignment: Alignment) -> Result<Self, OrdiseqError> {
+        let offset = match alignment {
+            Alignment::EndTime => self.end_time(),
+            Alignment::NextBar => self.length(),
+        };
+        self.merge(other, offset)
+    }
+
+    /// Returns this sequence playing at the same time as another one,
+    /// converted to the resolution of this sequence if needed.
+    ///
+    /// Elements of `other` starting at the same time as elements of
+    /// this sequence are placed after them. Automation breakpoints of
+    /// `other` replace the ones of this sequence at the same time. The
+    /// same channel and settings rules as `concat` apply.
+    pub fn overlay(&self, other: &Sequence) -> Result<Self, OrdiseqError> {
+        self.merge(other, Time { ticks: 0 })
+    }
+
+    /// Returns a copy of the sequence with the elements and automation
+    /// of another sequence added, moved later by `offset`.
+    fn merge(&self, other: &Sequence, offset: Time) -> Result<Self, OrdiseqError> {
+        let mut other = match other.ticks_per_quarter_note() == self.ticks_per_quarter_note() {
+            true => other.clone(),
+            false => other
+                .clone()
+                .with_resolution(self.ticks_per_quarter_note())?,
+        };
+        other.shift(TimeDelta::new(offset.ticks as i64))?;
+        let mut merged = self.clone();
+        // What plays on the channel of `other` keeps it
+        let other_channel = (other.channel != self.channel).then_some(other.channel);
+        for (time, elements) in other.elements {
+            for mut element in elements {
+                for note in element.notes_mut() {
+                    note.channel = note.channel.or(other_channel);
+                }
+                merged.insert(time, element);
+            }
+        }
+        for (channel, program) in other.programs {
+            merged
+                .programs
+                .entry(channel.or(other_channel))
+                .or_insert(program);
+        }
+        for (channel, range) in other.pitch_bend_ranges {
+            match merged.pitch_bend_ranges.entry(channel.or(other_channel)) {
+                Entry::Occupied(entry) if *entry.get() != range => {
+                    return Err(OrdiseqError::InvalidEdit(format!(
+                        "The pitch bend ranges of the sequences differ ({} and {range} semitones)",
+                        entry.get()
+                    )))
+                }
+                Entry::Occupied(_) => {}
+                Entry::Vacant(entry) => {
+                    entry.insert(range);
+                }
+            }
+        }
+        for ((channel, target), lane) in other.automation {
+            match merged.automation.entry((channel.or(other_channel), target)) {
+                Entry::Occupied(mut entry) => entry.get_mut().merge(&lane),
+                Entry::Vacant(entry) => {
+                    entry.insert(lane);
+                }
+            }
+        }
+        // Pitch bends are kept in semitones, so they only need to fit
+        // in the range of their channel
+        for ((channel, target), lane) in &merged.automation {
+            if *target == AutomationTarget::PitchBend {
+                let range = merged
+                    .pitch_bend_ranges
+                    .get(channel)
+                    .copied()
+                    .unwrap_or(DEFAULT_PITCH_BEND_RANGE);
+                for (time, bend, _) in lane.points() {
+                    check_pitch_bend(time, bend, range)?;
+                }
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Quantizes the notes of the sequence to a grid.
+    ///
+    /// Elements that end up starting at the same time are kept in their
+    /// original order. The sequence is left unchanged if the grid cannot
+    /// be represented at the sequence resolution.
+    pub fn quantize(&mut self, quantize: &Quantize) -> Result<(), OrdiseqError> {
+        let ticks_per_quarter_note = self.ticks_per_quarter_note();
+        let step = quantize.step(ticks_per_quarter_note)?;
+
+        let mut elements: BTreeMap<Time, Vec<SequenceElement>> = BTreeMap::new();
+        for (time, element) in self.elements() {
+            let start = quantize.quantize_time(time, ticks_per_quarter_note)?;
+            let mut element = element.clone();
+            if !quantize.preserve_length {
+                for note in element.notes_mut() {
+                    let mut end = time.saturating_add_ticks(note.duration.ticks);
+                    if quantize.ends {
+                        end = quantize.quantize_time(end, ticks_per_quarter_note)?;
+                    }
+                    note.duration = if end > start {
+                        Time {
+                            ticks: end.ticks - start.ticks,
+                        }
+                    } else {
+                        step
+                    };
+                }
+            }
+            elements.entry(start).or_default().push(element);
+        }
+        self.elements = elements;
+        Ok(())
+    }
+
+    /// Randomly varies the timing, velocity and duration of the stored
+    /// notes. Use `set_humanize` instead to vary them only when
+    /// rendering.
+    ///
+    /// All the notes of a chord share the same timing offset. Elements
+    /// that end up starting at the same time are kept in their original
+    /// order.
+    pub fn apply_humanize(&mut self, humanize: &Humanize) {
+        let mut rng = humanize.rng();
+        let mut elements: BTreeMap<Time, Vec<SequenceElement>> = BTreeMap::new();
+        for (time, element) in self.elements() {
+            let mut element = element.clone();
+            let mut start = time;
+            if !(humanize.preserve_downbeats && self.meter_map.is_downbeat(time)) {
+                start = humanize.humanize_time(&mut rng, time);
+                for note in element.notes_mut() {
+                    note.velocity = humanize.humanize_velocity(&mut rng, note.velocity);
+                    note.duration = humanize.humanize_duration(&mut rng, note.duration);
+                }
+            }
+            elements.entry(start).or_default().push(element);
+        }
+        self.elements = elements;
+    }
 
+    /// Converts the sequence into a MIDI `Smf` (Standard MIDI File).
+    pub fn to_midi(&self) -> Smf<'_> {
         Smf {
             header: midly::Header {
                 format: Format::SingleTrack,
-                timing: Timing::Metrical(self.ppq().into()),
+                timing: Timing::Metrical((self.ticks_per_quarter_note() as u16).into()),
             },
-            tracks: vec![track],
+            tracks: vec![to_track(self.midi_events())],
         }
     }
 
-    fn ppq(&self) -> u16 {
-        calculate_tpqn(self.time_signature).unwrap_or(96)
+    /// Exports the sequence to a MIDI file timed in SMPTE frames
+    /// instead of ticks. Every event is placed at its wall clock time
+    /// according to the tempo map.
+    ///
+    /// Returns an error if `ticks_per_frame` is zero, or if an event is
+    /// too far from the start to be counted in ticks.
+    pub fn to_midi_timecode(
+        &self,
+        frame_rate: FrameRate,
+        ticks_per_frame: u8,
+    ) -> Result<Smf<'_>, OrdiseqError> {
+        let events = to_timecode_events(
+            self.midi_events(),
+            &self.tempo_map,
+            frame_rate,
+            ticks_per_frame,
+        )?;
+        Ok(Smf {
+            header: midly::Header {
+                format: Format::SingleTrack,
+                timing: Timing::Timecode(frame_rate.to_midi(), ticks_per_frame),
+            },
+            tracks: vec![to_track(events)],
+        })
+    }
+
+    /// Returns the MIDI events of the sequence at absolute tick positions.
+    fn midi_events(&self) -> Vec<(u32, TrackEventKind<'_>)> {
+        let mut events = Vec::new();
+
+        // Add the time signature events
+        events.extend(self.meter_map.midi_events());
+
+        // Add the tempo events
+        events.extend(self.tempo_map.midi_events());
+
+        events.extend(self.channel_events());
+        events
+    }
+
+    /// Returns the channel events of the sequence at absolute tick
+    /// positions, without any meta events: the program selection, the
+    /// pitch bend range, the automation and the notes.
+    pub(crate) fn channel_events(&self) -> Vec<(u32, TrackEventKind<'static>)> {
+        let mut events = Vec::new();
+
+        for (channel, program) in &self.programs {
+            events.extend(program.midi_events(0, channel.unwrap_or(self.channel)));
+        }
+
+        for (channel, range) in &self.pitch_bend_ranges {
+            events.extend(pitch_bend_range_events(
+                0,
+                channel.unwrap_or(self.channel),
+                *range,
+            ));
+        }
+
+        for ((channel, _), lane) in &self.automation {
+            let pitch_bend_range = self
+                .pitch_bend_ranges
+                .get(channel)
+                .copied()
+                .unwrap_or(DEFAULT_PITCH_BEND_RANGE);
+            events.extend(lane.midi_events(
+                channel.unwrap_or(self.channel),
+                self.automation_density,
+                pitch_bend_range,
+            ));
+        }
+
+        // Collect all note-on and note-off events, in time order so that
+        // humanization is reproducible
+        let mut rng = self.humanize.as_ref().map(Humanize::rng);
+        for (time, element) in self.elements() {
+            let humanize = self.humanize.as_ref().filter(|humanize| {
+                !(humanize.preserve_downbeats && self.meter_map.is_downbeat(time))
+            });
+            // All the notes of a chord share the same timing offset
+            let offset = match (humanize, &mut rng) {
+                (Some(humanize), Some(rng)) => humanize.humanize_time(rng, time) - time,
+                _ => TimeDelta::new(0),
+            };
+            for sequence_note in element.notes() {
+                let (start, mut velocity) = self.apply_groove(time, sequence_note.velocity);
+                let start = start.saturating_add(offset);
+                let mut duration = sequence_note.duration;
+                if let (Some(humanize), Some(rng)) = (humanize, &mut rng) {
+                    velocity = humanize.humanize_velocity(rng, velocity);
+                    duration = humanize.humanize_duration(rng, duration);
+                }
+                let key = sequence_note.note.midi_value().into();
+                let channel = sequence_note.channel.unwrap_or(self.channel).value().into();
+
+                // Note On
+                events.push((
+                    start.ticks,
+                    TrackEventKind::Midi {
+                        channel,
+                        message: MidiMessage::NoteOn {
+                            key,
+                            vel: ((velocity * 127.0).round() as u8).into(),
+                        },
+                    },
+                ));
+
+                // Note Off
+                events.push((
+                    start.saturating_add_ticks(duration.ticks).ticks,
+                    TrackEventKind::Midi {
+                        channel,
+                        message: MidiMessage::NoteOff { key, vel: 0.into() },
+                    },
+                ));
+            }
+        }
+        events
+    }
+
+    /// Returns a copy of the sequence at a different resolution.
+    ///
+    /// Every note, duration, tempo change and time signature is moved
+    /// to the matching tick at the new resolution, rounding to the
+    /// nearest tick.
+    pub fn with_resolution(mut self, ticks_per_quarter_note: u32) -> Result<Self, OrdiseqError> {
+        check_resolution(ticks_per_quarter_note)?;
+        if let Some(groove) = &self.groove {
+            groove.grid.to_ticks(ticks_per_quarter_note)?;
+        }
+        let from = self.ticks_per_quarter_note();
+        self.meter_map = self.meter_map.with_resolution(ticks_per_quarter_note)?;
+        let rescale = |time: Time| time.rescale(from, ticks_per_quarter_note);
+        let mut elements: BTreeMap<Time, Vec<SequenceElement>> = BTreeMap::new();
+        for (time, mut element) in std::mem::take(&mut self.elements)
+            .into_iter()
+            .flat_map(|(time, elements)| elements.into_iter().map(move |element| (time, element)))
+        {
+            for note in element.notes_mut() {
+                note.duration = rescale(note.duration);
+            }
+            elements.entry(rescale(time)).or_default().push(element);
+        }
+        self.elements = elements;
+        for lane in self.automation.values_mut() {
+            *lane = lane.with_resolution(from, ticks_per_quarter_note);
+        }
+        self.automation_density = Time {
+            ticks: rescale(self.automation_density).ticks.max(1),
+        };
+        self.tempo_map = self.tempo_map.with_resolution(ticks_per_quarter_note)?;
+        Ok(self)
+    }
+
+    /// Iterates over every element with its start time, in time order,
+    /// and in insertion order for elements at the same time.
+    pub fn elements(&self) -> impl Iterator<Item = (Time, &SequenceElement)> {
+        self.elements_in(..)
+    }
+
+    /// Iterates over the elements starting within a range of times,
+    /// such as `Time { ticks: 384 }..Time { ticks: 768 }`. A range that
+    /// ends before it starts has no elements.
+    pub fn elements_in<R: RangeBounds<Time>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (Time, &SequenceElement)> {
+        let empty = (
+            Bound::Included(Time { ticks: 0 }),
+            Bound::Excluded(Time { ticks: 0 }),
+        );
+        self.elements
+            .range(ordered_range(&range).unwrap_or(empty))
+            .flat_map(|(time, elements)| elements.iter().map(move |element| (*time, element)))
+    }
+
+    /// Returns the notes sounding at a time, with their start times:
+    /// the notes that start at or before the time and end after it.
+    ///
+    /// Times are the stored times, before any groove is applied.
+    pub fn notes_at(&self, time: Time) -> Vec<(Time, &SequenceNote)> {
+        self.elements_in(..=time)
+            .flat_map(|(start, element)| element.notes().iter().map(move |note| (start, note)))
+            .filter(|(start, note)| {
+                start.ticks as u64 + note.duration.ticks as u64 > time.ticks as u64
+            })
+            .collect()
+    }
+
+    /// Returns the time at which the last note ends, or zero for an
+    /// empty sequence. A note lasting past the largest time is counted
+    /// as ending at it.
+    pub fn end_time(&self) -> Time {
+        self.elements()
+            .flat_map(|(start, element)| {
+                element
+                    .notes()
+                    .iter()
+                    .map(move |note| start.saturating_add_ticks(note.duration.ticks))
+            })
+            .max()
+            .unwrap_or(Time { ticks: 0 })
+    }
+
+    /// Returns the number of bars the notes span, counting from bar 1.
+    /// A pickup (bar 0) is not counted.
+    pub fn bar_count(&self) -> u32 {
+        let end = self.end_time();
+        if end <= self.meter_map.pickup() {
+            return 0;
+        }
+        self.meter_map.bar_at(Time {
+            ticks: end.ticks - 1,
+        })
+    }
+
+    /// Returns the length of the sequence in whole bars: the time at
+    /// which the bar of the last note ends, pickup included. A bar
+    /// ending past the largest time ends at it.
+    pub fn length(&self) -> Time {
+        self.meter_map
+            .checked_bar_start(self.bar_count() + 1)
+            .unwrap_or(Time { ticks: u32::MAX })
+    }
+
+    /// Returns the lowest note of the sequence, or `None` if it is
+    /// empty.
+    pub fn lowest_note(&self) -> Option<Note> {
+        self.all_notes().min_by_key(|note| note.midi_value())
+    }
+
+    /// Returns the highest note of the sequence, or `None` if it is
+    /// empty.
+    pub fn highest_note(&self) -> Option<Note> {
+        self.all_notes().max_by_key(|note| note.midi_value())
+    }
+
+    fn all_notes(&self) -> impl Iterator<Item = Note> + '_ {
+        self.elements()
+            .flat_map(|(_, element)| element.notes().iter().map(|note| note.note))
+    }
+
+    /// Returns the start time and velocity of a note after applying the
+    /// groove of the sequence.
+    fn apply_groove(&self, time: Time, velocity: f32) -> (Time, f32) {
+        match &self.groove {
+            Some(groove) => groove
+                .apply(time, velocity, self.ticks_per_quarter_note())
+                .expect("The groove grid is checked against the sequence resolution"),
+            None => (time, velocity),
+        }
+    }
+
+    /// Returns the resolution of the sequence, used for every tick it
+    /// stores and for the MIDI file header.
+    pub fn ticks_per_quarter_note(&self) -> u32 {
+        self.meter_map.ticks_per_quarter_note()
     }
 
     pub fn title(&self) -> String {
         self.title.clone()
     }
+    /// Returns the time signature of the first bar.
     pub fn time_signature(&self) -> TimeSignature {
-        self.time_signature
+        self.meter_map.time_signature_at_bar(1)
+    }
+    /// Returns the time signature in force at a specific time.
+    pub fn time_signature_at(&self, time: Time) -> TimeSignature {
+        self.meter_map.time_signature_at(time)
+    }
+    pub fn meter_map(&self) -> &MeterMap {
+        &self.meter_map
+    }
+    pub fn groove(&self) -> Option<&Groove> {
+        self.groove.as_ref()
+    }
+    pub fn humanize(&self) -> Option<&Humanize> {
+        self.humanize.as_ref()
+    }
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+    pub fn program(&self) -> Option<&Program> {
+        self.programs.get(&None)
+    }
+    /// Returns the program selected on a channel, or on the sequence
+    /// channel for `None`.
+    pub fn program_on_channel(&self, channel: Option<Channel>) -> Option<&Program> {
+        self.programs.get(&channel)
+    }
+    pub fn automation(&self, target: AutomationTarget) -> Option<&AutomationLane> {
+        self.automation.get(&(None, target))
+    }
+    /// Returns the automation lane of a target on a channel, or on the
+    /// sequence channel for `None`.
+    pub fn automation_on_channel(
+        &self,
+        channel: Option<Channel>,
+        target: AutomationTarget,
+    ) -> Option<&AutomationLane> {
+        self.automation.get(&(channel, target))
+    }
+    pub fn pitch_bend_range(&self) -> Option<f32> {
+        self.pitch_bend_ranges.get(&None).copied()
+    }
+    /// Returns the pitch bend range of a channel, or of the sequence
+    /// channel for `None`.
+    pub fn pitch_bend_range_on_channel(&self, channel: Option<Channel>) -> Option<f32> {
+        self.pitch_bend_ranges.get(&channel).copied()
+    }
+    pub fn automation_density(&self) -> Time {
+        self.automation_density
+    }
+    /// Converts a time into a bars:beats:ticks `Position`.
+    pub fn position_at(&self, time: Time) -> Position {
+        Position::from_time(time, &self.meter_map)
+    }
+    pub fn tempo_map(&self) -> &TempoMap {
+        &self.tempo_map
+    }
+    pub fn tempo_map_mut(&mut self) -> &mut TempoMap {
+        &mut self.tempo_map
+    }
+}
+
+// Concatenation, aligned to the end time: see `concat`.
+impl Add for Sequence {
+    type Output = Result<Sequence, OrdiseqError>;
+
+    fn add(self, rhs: Sequence) -> Self::Output {
+        self.concat(&rhs, Alignment::EndTime)
+    }
+}
+
+// Chained concatenation, so that `a + b + c` works. An error from an
+// earlier step is passed on.
+impl Add<Sequence> for Result<Sequence, OrdiseqError> {
+    type Output = Result<Sequence, OrdiseqError>;
+
+    fn add(self, rhs: Sequence) -> Self::Output {
+        self?.concat(&rhs, Alignment::EndTime)
+    }
+}
+
+// Overlay: see `overlay`.
+impl BitOr for Sequence {
+    type Output = Result<Sequence, OrdiseqError>;
+
+    fn bitor(self, rhs: Sequence) -> Self::Output {
+        self.overlay(&rhs)
+    }
+}
+
+// Chained overlay, so that `a | b | c` works. An error from an earlier
+// step is passed on.
+impl BitOr<Sequence> for Result<Sequence, OrdiseqError> {
+    type Output = Result<Sequence, OrdiseqError>;
+
+    fn bitor(self, rhs: Sequence) -> Self::Output {
+        self?.overlay(&rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groove::SwingGrid;
+    use crate::time::{common_time, Division, NoteValue};
+    use klib::core::named_pitch::NamedPitch;
+    use klib::core::note::{FSharp, A, C, D, E, F, G};
+    use klib::core::octave::Octave;
+    use midly::PitchBend;
+
+    fn durations_at(seq: &Sequence, ticks: u32) -> Vec<u32> {
+        seq.elements[&Time { ticks }]
+            .iter()
+            .flat_map(|element| element.notes())
+            .map(|note| note.duration.ticks)
+            .collect()
+    }
+
+    fn quarter(ticks_per_quarter_note: u32) -> Time {
+        Time {
+            ticks: ticks_per_quarter_note,
+        }
+    }
+
+    #[test]
+    fn test_header_matches_note_resolution() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_note(Time { ticks: 96 }, C, 0.5, quarter(96))
+            .unwrap();
+        let smf = seq.to_midi();
+        assert_eq!(smf.header.timing, Timing::Metrical(96.into()));
+    }
+
+    #[test]
+    fn test_repeat_cycle() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_note(Time { ticks: 0 }, C, 0.5, quarter(96))
+            .unwrap();
+        seq.add_note(Time { ticks: 192 }, E, 0.5, quarter(96))
+            .unwrap();
+        seq.add_note(Time { ticks: 1000 }, E, 0.5, quarter(96))
+            .unwrap();
+        // Three beats looped against a four beat part:
+        let cycle = Cycle::new(3, quarter(96)).unwrap();
+        let four = Cycle::new(4, quarter(96)).unwrap();
+        seq.repeat_cycle(cycle, Cycle::common_length(&[cycle, four]).unwrap());
+        assert_eq!(
+            seq.elements
+                .keys()
+                .map(|time| time.ticks)
+                .collect::<Vec<_>>(),
+            vec![0, 192, 288, 480, 576, 768, 864, 1056]
+        );
+    }
+
+    #[test]
+    fn test_to_midi_timecode() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        // At 120 BPM a quarter note lasts half a second:
+        seq.add_note(Time { ticks: 96 }, C, 0.5, quarter(96))
+            .unwrap();
+        assert!(seq.to_midi_timecode(FrameRate::Fps25, 0).is_err());
+        let smf = seq.to_midi_timecode(FrameRate::Fps25, 40).unwrap();
+        assert_eq!(smf.header.timing, Timing::Timecode(midly::Fps::Fps25, 40));
+        let note_times: Vec<u32> = smf.tracks[0]
+            .iter()
+            .scan(0, |ticks, event| {
+                *ticks += event.delta.as_int();
+                Some((*ticks, event.kind))
+            })
+            .filter(|(_, kind)| matches!(kind, TrackEventKind::Midi { .. }))
+            .map(|(ticks, _)| ticks)
+            .collect();
+        // 25 frames * 40 ticks per second:
+        assert_eq!(note_times, vec![500, 1000]);
+
+        // About 240 days at 120 BPM is too many frame ticks to count:
+        seq.add_note(
+            Time {
+                ticks: 4_000_000_000,
+            },
+            C,
+            0.5,
+            quarter(96),
+        )
+        .unwrap();
+        assert!(seq.to_midi_timecode(FrameRate::Fps25, 40).is_err());
+    }
+
+    #[test]
+    fn test_with_resolution() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_note(Time { ticks: 96 }, C, 0.5, quarter(96))
+            .unwrap();
+        seq.add_chord(Time { ticks: 192 }, vec![(E, 0.5, Time { ticks: 48 })])
+            .unwrap();
+        seq.set_tempo(Time { ticks: 384 }, Tempo::from_bpm(90.0).unwrap());
+        let duration = seq.tempo_map().to_duration(Time { ticks: 480 });
+
+        let seq = seq.with_resolution(480).unwrap();
+        assert_eq!(seq.ticks_per_quarter_note(), 480);
+        assert_eq!(seq.time_signature().ticks_per_quarter_note(), 480);
+        assert_eq!(
+            seq.elements.keys().copied().collect::<Vec<_>>(),
+            vec![Time { ticks: 480 }, Time { ticks: 960 }]
+        );
+        match &seq.elements[&Time { ticks: 960 }][0] {
+            SequenceElement::Chord(chord) => {
+                assert_eq!(chord.sequence_notes[0].duration, Time { ticks: 240 })
+            }
+            element => panic!("Expected a chord: {element:?}"),
+        }
+        assert_eq!(seq.tempo_map().to_duration(Time { ticks: 2400 }), duration);
+        assert_eq!(seq.to_midi().header.timing, Timing::Metrical(480.into()));
+    }
+
+    #[test]
+    fn test_quantize() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_note(Time { ticks: 3 }, C, 0.5, Time { ticks: 40 })
+            .unwrap();
+        seq.add_note(Time { ticks: 95 }, E, 0.5, Time { ticks: 50 })
+            .unwrap();
+        seq.add_note(Time { ticks: 101 }, C, 0.5, Time { ticks: 20 })
+            .unwrap();
+
+        let mut preserved = seq.clone();
+        preserved
+            .quantize(&Quantize::new(NoteValue::new(Division::Eighth)))
+            .unwrap();
+        assert_eq!(
+            preserved.elements.keys().copied().collect::<Vec<_>>(),
+            vec![Time { ticks: 0 }, Time { ticks: 96 }]
+        );
+        // The two notes close to beat 2 both start on it, in order:
+        assert_eq!(durations_at(&preserved, 96), vec![50, 20]);
+        assert_eq!(preserved.elements[&Time { ticks: 96 }].len(), 2);
+
+        let quantize = Quantize::new(NoteValue::new(Division::Eighth))
+            .preserve_length(false)
+            .ends(true);
+        seq.quantize(&quantize).unwrap();
+        assert_eq!(durations_at(&seq, 0), vec![48]);
+        assert_eq!(durations_at(&seq, 96), vec![48, 48]);
+    }
+
+    #[test]
+    fn test_humanize() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        for beat in 0..8 {
+            seq.add_note(Time { ticks: beat * 96 }, C, 0.5, Time { ticks: 48 })
+                .unwrap();
+        }
+        let humanize = Humanize::new(42)
+            .timing(Time { ticks: 8 })
+            .velocity(0.1)
+            .duration(0.25)
+            .preserve_downbeats(true);
+
+        let mut a = seq.clone();
+        let mut b = seq.clone();
+        a.apply_humanize(&humanize);
+        b.apply_humanize(&humanize);
+        assert_eq!(a.elements, b.elements);
+        assert_ne!(a.elements, seq.elements);
+
+        // The downbeats of bars 1 and 2 are untouched:
+        for ticks in [0, 384] {
+            assert_eq!(a.elements[&Time { ticks }], seq.elements[&Time { ticks }]);
+        }
+    }
+
+    #[test]
+    fn test_set_humanize() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        for beat in 0..8 {
+            seq.add_note(Time { ticks: beat * 96 }, C, 0.5, Time { ticks: 48 })
+                .unwrap();
+        }
+        let plain = seq.channel_events();
+        seq.set_humanize(
+            Humanize::new(42)
+                .timing(Time { ticks: 8 })
+                .velocity(0.1)
+                .preserve_downbeats(true),
+        );
+        let humanized = seq.channel_events();
+        // The notes are only varied when rendering, the same way every
+        // time:
+        assert_eq!(seq.channel_events(), humanized);
+        assert_ne!(humanized, plain);
+        assert_eq!(notes(&seq)[1], (96, 60, 48));
+        // The downbeat of bar 1 is untouched:
+        assert_eq!(humanized[..2], plain[..2]);
+        seq.clear_humanize();
+        assert_eq!(seq.channel_events(), plain);
+    }
+
+    #[test]
+    fn test_same_start_time() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_note(Time { ticks: 96 }, C, 0.5, Time { ticks: 96 })
+            .unwrap();
+        seq.add_note(Time { ticks: 96 }, E, 0.5, Time { ticks: 48 })
+            .unwrap();
+        seq.add_chord(Time { ticks: 96 }, vec![(C, 0.5, Time { ticks: 24 })])
+            .unwrap();
+        assert_eq!(durations_at(&seq, 96), vec![96, 48, 24]);
+        // Both overlapping melodies are rendered:
+        let note_ons = seq
+            .midi_events()
+            .iter()
+            .filter(|(_, kind)| {
+                matches!(
+                    kind,
+                    TrackEventKind::Midi {
+                        message: MidiMessage::NoteOn { .. },
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(note_ons, 3);
+
+        seq.replace_note(Time { ticks: 96 }, E, 0.5, Time { ticks: 12 })
+            .unwrap();
+        assert_eq!(durations_at(&seq, 96), vec![12]);
+        assert_eq!(seq.remove_at(Time { ticks: 96 }).unwrap(), 1);
+        assert_eq!(seq.remove_at(Time { ticks: 96 }).unwrap(), 0);
+        assert!(seq.elements.is_empty());
+    }
+
+    #[test]
+    fn test_channels() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.set_channel(Channel::new(3).unwrap());
+        seq.set_program(Program::new(40).unwrap().with_bank(1).unwrap());
+        seq.add_note(Time { ticks: 0 }, C, 0.5, quarter(96))
+            .unwrap();
+        seq.add_note_on_channel(
+            Time { ticks: 96 },
+            E,
+            0.5,
+            quarter(96),
+            Channel::new(5).unwrap(),
+        )
+        .unwrap();
+        let messages: Vec<(u8, MidiMessage)> = seq
+            .channel_events()
+            .into_iter()
+            .filter_map(|(_, kind)| match kind {
+                TrackEventKind::Midi { channel, message } => Some((channel.as_int(), message)),
+                _ => None,
+            })
+            .collect();
+        // Bank select and program change first, on the sequence channel:
+        assert_eq!(
+            messages[..3]
+                .iter()
+                .map(|(channel, _)| *channel)
+                .collect::<Vec<_>>(),
+            vec![3, 3, 3]
+        );
+        assert_eq!(
+            messages[2].1,
+            MidiMessage::ProgramChange { program: 40.into() }
+        );
+        let channels: Vec<u8> = messages[3..].iter().map(|(channel, _)| *channel).collect();
+        assert_eq!(channels, vec![3, 3, 5, 5]);
+    }
+
+    #[test]
+    fn test_automation() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.set_channel(Channel::new(1).unwrap());
+        seq.add_note(Time { ticks: 96 }, C, 0.5, quarter(96))
+            .unwrap();
+        seq.add_automation_point(11, Time { ticks: 0 }, 0.0, Interpolation::Linear)
+            .unwrap();
+        seq.add_automation_point(11, Time { ticks: 96 }, 1.0, Interpolation::Step)
+            .unwrap();
+        seq.set_automation_density(NoteValue::new(Division::Sixteenth))
+            .unwrap();
+        assert!(seq
+            .add_automation_point(120, Time { ticks: 0 }, 0.0, Interpolation::Step)
+            .is_err());
+
+        let smf = seq.to_midi();
+        let messages: Vec<(u32, MidiMessage)> = smf.tracks[0]
+            .iter()
+            .scan(0, |ticks, event| {
+                *ticks += event.delta.as_int();
+                Some((*ticks, event.kind))
+            })
+            .filter_map(|(ticks, kind)| match kind {
+                TrackEventKind::Midi { channel, message } => {
+                    assert_eq!(channel.as_int(), 1);
+                    Some((ticks, message))
+                }
+                _ => None,
+            })
+            .collect();
+        let controller = |value: u8| MidiMessage::Controller {
+            controller: 11.into(),
+            value: value.into(),
+        };
+        assert_eq!(
+            messages[..4],
+            [
+                (0, controller(0)),
+                (24, controller(32)),
+                (48, controller(64)),
+                (72, controller(95)),
+            ]
+        );
+        // The last breakpoint is sent before the note starting with it:
+        assert_eq!(messages[4], (96, controller(127)));
+        assert!(matches!(messages[5], (96, MidiMessage::NoteOn { .. })));
+
+        let seq = seq.with_resolution(480).unwrap();
+        assert_eq!(seq.automation_density(), Time { ticks: 120 });
+        assert_eq!(
+            seq.automation(AutomationTarget::Controller(11))
+                .unwrap()
+                .points()
+                .last()
+                .unwrap()
+                .0,
+            Time { ticks: 480 }
+        );
+    }
+
+    #[test]
+    fn test_pitch_bend_and_pressure() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.set_pitch_bend_range(12.0).unwrap();
+        seq.add_note(Time { ticks: 0 }, C, 0.5, quarter(96))
+            .unwrap();
+        seq.add_pitch_bend(Time { ticks: 0 }, 0.0, Interpolation::Linear)
+            .unwrap();
+        seq.add_pitch_bend(Time { ticks: 96 }, 12.0, Interpolation::Step)
+            .unwrap();
+        assert!(seq
+            .add_pitch_bend(Time { ticks: 96 }, 13.0, Interpolation::Step)
+            .is_err());
+        // The range cannot be narrowed below the bends already added:
+        assert!(seq.set_pitch_bend_range(6.0).is_err());
+        assert_eq!(seq.pitch_bend_range(), Some(12.0));
+        // The range is sent to the nearest cent:
+        let mut widest = Sequence::new("test", common_time()).unwrap();
+        assert!(widest.set_pitch_bend_range(127.99).is_ok());
+        assert!(widest.set_pitch_bend_range(127.999).is_err());
+        assert!(widest.set_pitch_bend_range(0.001).is_err());
+        assert_eq!(widest.pitch_bend_range(), Some(127.99));
+        seq.add_channel_pressure(Time { ticks: 48 }, 1.0, Interpolation::Step)
+            .unwrap();
+        seq.add_aftertouch(Time { ticks: 48 }, C, 0.5, Interpolation::Step)
+            .unwrap();
+        seq.set_automation_density(Time { ticks: 48 }).unwrap();
+
+        // Write the file and read it back:
+        let mut bytes = Vec::new();
+        seq.to_midi().write(&mut bytes).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        let messages: Vec<(u32, MidiMessage)> = smf.tracks[0]
+            .iter()
+            .scan(0, |ticks, event| {
+                *ticks += event.delta.as_int();
+                Some((*ticks, event.kind))
+            })
+            .filter_map(|(ticks, kind)| match kind {
+                TrackEventKind::Midi { message, .. } => Some((ticks, message)),
+                _ => None,
+            })
+            .collect();
+        // The pitch bend range (RPN 0) is set first:
+        let controller = |controller: u8, value: u8| MidiMessage::Controller {
+            controller: controller.into(),
+            value: value.into(),
+        };
+        assert_eq!(
+            messages[..6]
+                .iter()
+                .map(|(_, message)| *message)
+                .collect::<Vec<_>>(),
+            vec![
+                controller(101, 0),
+                controller(100, 0),
+                controller(6, 12),
+                controller(38, 0),
+                controller(101, 127),
+                controller(100, 127),
+            ]
+        );
+        let bends: Vec<(u32, u16)> = messages
+            .iter()
+            .filter_map(|(ticks, message)| match message {
+                MidiMessage::PitchBend { bend } => Some((*ticks, bend.0.as_int())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(bends, vec![(0, 8192), (48, 12287), (96, 16383)]);
+        assert!(messages.contains(&(48, MidiMessage::ChannelAftertouch { vel: 127.into() })));
+        assert!(messages.contains(&(
+            48,
+            MidiMessage::Aftertouch {
+                key: C.midi_value().into(),
+                vel: 64.into()
+            }
+        )));
+    }
+
+    #[test]
+    fn test_pitch_bend_range_after_bends() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_pitch_bend(Time { ticks: 0 }, 1.0, Interpolation::Step)
+            .unwrap();
+        let bends = |seq: &Sequence| -> Vec<u16> {
+            seq.channel_events()
+                .into_iter()
+                .filter_map(|(_, kind)| match kind {
+                    TrackEventKind::Midi {
+                        message: MidiMessage::PitchBend { bend },
+                        ..
+                    } => Some(bend.0.as_int()),
+                    _ => None,
+                })
+                .collect()
+        };
+        // Half of the default range of two semitones:
+        assert_eq!(bends(&seq), vec![12287]);
+        // The bend keeps its semitones over a wider range:
+        seq.set_pitch_bend_range(4.0).unwrap();
+        assert_eq!(bends(&seq), vec![10239]);
+    }
+
+    fn pitches_at(seq: &Sequence, ticks: u32) -> Vec<u8> {
+        seq.elements[&Time { ticks }]
+            .iter()
+            .flat_map(|element| element.notes())
+            .map(|note| note.note.midi_value())
+            .collect()
+    }
+
+    #[test]
+    fn test_transpose_chords() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_note(Time { ticks: 0 }, C, 0.5, quarter(96))
+            .unwrap();
+        seq.add_chord(
+            Time { ticks: 96 },
+            vec![
+                (C, 0.5, quarter(96)),
+                (E, 0.5, quarter(96)),
+                (G, 0.5, quarter(96)),
+            ],
+        )
+        .unwrap();
+        let seq = seq.transpose(2).unwrap();
+        assert_eq!(pitches_at(&seq, 0), vec![62]);
+        assert_eq!(pitches_at(&seq, 96), vec![62, 66, 69]);
+    }
+
+    #[test]
+    fn test_transpose_range_and_voice() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        for ticks in [0, 384] {
+            // Added from the top down, to check voices follow pitch:
+            seq.add_chord(
+                Time { ticks },
+                vec![
+                    (G, 0.5, quarter(96)),
+                    (E, 0.5, quarter(96)),
+                    (C, 0.5, quarter(96)),
+                ],
+            )
+            .unwrap();
+        }
+        seq.transpose_range(Time { ticks: 384 }.., -12).unwrap();
+        assert_eq!(pitches_at(&seq, 0), vec![67, 64, 60]);
+        assert_eq!(pitches_at(&seq, 384), vec![55, 52, 48]);
+
+        seq.transpose_voice(.., Voice::Lowest, -12).unwrap();
+        assert_eq!(pitches_at(&seq, 0), vec![67, 64, 48]);
+        seq.transpose_voice(..Time { ticks: 384 }, Voice::FromBottom(1), 1)
+            .unwrap();
+        assert_eq!(pitches_at(&seq, 0), vec![67, 65, 48]);
+        seq.transpose_voice(.., Voice::FromBottom(3), 1).unwrap();
+        assert_eq!(pitches_at(&seq, 384), vec![55, 52, 36]);
+        seq.transpose_voice(.., Voice::Highest, 1).unwrap();
+        assert_eq!(pitches_at(&seq, 384), vec![56, 52, 36]);
+
+        // Single notes starting at the same time are voices too:
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        for note in [G, C, E] {
+            seq.add_note(Time { ticks: 0 }, note, 0.5, quarter(96))
+                .unwrap();
+        }
+        seq.transpose_voice(.., Voice::Lowest, -12).unwrap();
+        assert_eq!(pitches_at(&seq, 0), vec![67, 48, 64]);
+        seq.transpose_voice(.., Voice::FromBottom(1), 1).unwrap();
+        assert_eq!(pitches_at(&seq, 0), vec![67, 48, 65]);
+
+        // Ranges that end before they start:
+        let (start, end) = (Time { ticks: 96 }, Time { ticks: 0 });
+        assert!(matches!(
+            seq.transpose_range(start..end, 1),
+            Err(OrdiseqError::InvalidEdit(_))
+        ));
+        assert!(matches!(
+            seq.transpose_voice(start..=end, Voice::Lowest, 1),
+            Err(OrdiseqError::InvalidEdit(_))
+        ));
+        // A range excluding both ends at the same time is empty:
+        seq.transpose_range((Bound::Excluded(end), Bound::Excluded(end)), 1)
+            .unwrap();
+        assert_eq!(pitches_at(&seq, 0), vec![67, 48, 65]);
+    }
+
+    #[test]
+    fn test_transpose_out_of_range() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_note(Time { ticks: 0 }, C, 0.5, quarter(96))
+            .unwrap();
+        seq.add_chord(
+            Time { ticks: 96 },
+            vec![(C, 0.5, quarter(96)), (G, 0.5, quarter(96))],
+        )
+        .unwrap();
+        // G4 + 61 semitones is MIDI 128:
+        match seq.clone().transpose(61) {
+            Err(OrdiseqError::NotesOutOfRange(notes)) => {
+                assert_eq!(
+                    notes,
+                    vec![OutOfRangeNote {
+                        time: Time { ticks: 96 },
+                        note: G
+                    }]
+                );
+            }
+            _ => panic!("Expected notes out of range"),
+        }
+        assert_eq!(pitches_at(&seq, 96), vec![60, 67]);
+
+        let mut clamped = seq.clone();
+        let notes = clamped
+            .transpose_with(.., None, 61, OutOfRange::Clamp)
+            .unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(pitches_at(&clamped, 96), vec![121, 127]);
+
+        // G#9 is above the range: wrap to the lowest G#, or fold to G#8
+        let mut wrapped = seq.clone();
+        wrapped
+            .transpose_with(.., None, 61, OutOfRange::WrapOctave)
+            .unwrap();
+        assert_eq!(pitches_at(&wrapped, 96), vec![121, 20]);
+        let mut folded = seq.clone();
+        folded
+            .transpose_with(.., None, 61, OutOfRange::FoldOctave)
+            .unwrap();
+        assert_eq!(pitches_at(&folded, 96), vec![121, 116]);
+
+        // C4 - 49 semitones is B-1, below C0:
+        let mut low = seq.clone();
+        low.transpose_with(..Time { ticks: 96 }, None, -49, OutOfRange::FoldOctave)
+            .unwrap();
+        assert_eq!(pitches_at(&low, 0), vec![23]);
+        low.transpose_with(..Time { ticks: 96 }, None, -12, OutOfRange::WrapOctave)
+            .unwrap();
+        assert_eq!(pitches_at(&low, 0), vec![119]);
+        assert_eq!(pitches_at(&low, 96), vec![60, 67]);
+    }
+
+    #[test]
+    fn test_position_before_the_pickup() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.set_pickup(quarter(96)).unwrap();
+        assert!(matches!(
+            seq.add_note(Position::new(0, 1, 0), C, 0.5, quarter(96)),
+            Err(OrdiseqError::InvalidPosition(_))
+        ));
+        assert!(seq
+            .add_automation_point(1, Position::new(0, 3, 0), 0.5, Interpolation::Step)
+            .is_err());
+        seq.add_note(Position::new(0, 4, 0), C, 0.5, quarter(96))
+            .unwrap();
+        assert_eq!(pitches_at(&seq, 0), vec![60]);
+    }
+
+    #[test]
+    fn test_notes_above_midi_range() {
+        // A9 would be MIDI 129:
+        let a9 = Note::new(NamedPitch::A, Octave::Nine);
+        assert!(a9.try_midi_value().is_err());
+        assert_eq!(a9.midi_value(), 127);
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        assert!(matches!(
+            seq.add_note(Time { ticks: 0 }, a9, 0.5, quarter(96)),
+            Err(OrdiseqError::NoteOutOfRange(_))
+        ));
+        assert!(seq
+            .add_chord(
+                Time { ticks: 0 },
+                vec![(C, 0.5, quarter(96)), (a9, 0.5, quarter(96))]
+            )
+            .is_err());
+        assert_eq!(seq.elements().count(), 0);
+        seq.add_note(Time { ticks: 0 }, C, 0.5, quarter(96))
+            .unwrap();
+        assert!(seq
+            .replace_note(Time { ticks: 0 }, a9, 0.5, quarter(96))
+            .is_err());
+        assert_eq!(pitches_at(&seq, 0), vec![60]);
+        // C4 + 68 semitones is MIDI 128:
+        assert!(C.transpose(68).is_err());
+        assert_eq!(C.transpose(7).unwrap(), G);
+    }
+
+    #[test]
+    fn test_transpose_diatonic() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_chord(
+            Time { ticks: 0 },
+            vec![
+                (D, 0.5, quarter(96)),
+                (F, 0.5, quarter(96)),
+                (A, 0.5, quarter(96)),
+            ],
+        )
+        .unwrap();
+        seq.add_note(Time { ticks: 96 }, FSharp, 0.5, quarter(96))
+            .unwrap();
+        let key = Key::from_scale_name(NamedPitch::D, "Dorian").unwrap();
+
+        // F# is not in D dorian:
+        assert!(seq.transpose_diatonic(&key, 2, OutOfScale::Error).is_err());
+        assert_eq!(pitches_at(&seq, 0), vec![62, 65, 69]);
+
+        seq.transpose_diatonic(&key, 2, OutOfScale::Snap).unwrap();
+        // D minor becomes F major, F# snaps to F and becomes A:
+        assert_eq!(pitches_at(&seq, 0), vec![65, 69, 72]);
+        assert_eq!(pitches_at(&seq, 96), vec![69]);
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        assert_eq!(seq.end_time(), Time { ticks: 0 });
+        assert_eq!(seq.bar_count(), 0);
+        assert_eq!(seq.lowest_note(), None);
+
+        seq.add_note(Time { ticks: 0 }, E, 0.5, quarter(96) * 2.0)
+            .unwrap();
+        seq.add_chord(
+            Time { ticks: 96 },
+            vec![(C, 0.5, quarter(96)), (G, 0.5, quarter(96) * 4.0)],
+        )
+        .unwrap();
+        seq.add_note(Time { ticks: 384 }, D, 0.5, quarter(96))
+            .unwrap();
+
+        assert_eq!(seq.elements().count(), 3);
+        let in_range: Vec<u32> = seq
+            .elements_in(Time { ticks: 96 }..Time { ticks: 384 })
+            .map(|(time, _)| time.ticks)
+            .collect();
+        assert_eq!(in_range, vec![96]);
+        let (start, end) = (Time { ticks: 384 }, Time { ticks: 96 });
+        assert_eq!(seq.elements_in(start..end).count(), 0);
+        assert_eq!(seq.elements_in(start..=end).count(), 0);
+        assert_eq!(
+            seq.elements_in((Bound::Excluded(end), Bound::Excluded(end)))
+                .count(),
+            0
+        );
+
+        let sounding: Vec<(u32, u8)> = seq
+            .notes_at(Time { ticks: 191 })
+            .into_iter()
+            .map(|(time, note)| (time.ticks, note.note().midi_value()))
+            .collect();
+        assert_eq!(sounding, vec![(0, 64), (96, 60), (96, 67)]);
+        // Notes ending at a time are no longer sounding:
+        assert_eq!(seq.notes_at(Time { ticks: 192 }).len(), 1);
+
+        assert_eq!(seq.end_time(), Time { ticks: 480 });
+        assert_eq!(seq.bar_count(), 2);
+        assert_eq!(seq.length(), Time { ticks: 768 });
+        assert_eq!(seq.lowest_note(), Some(C));
+        assert_eq!(seq.highest_note(), Some(G));
+
+        let (_, element) = seq.elements().nth(1).unwrap();
+        match element {
+            SequenceElement::Chord(chord) => {
+                assert_eq!(chord.notes()[1].duration(), Time { ticks: 384 });
+                assert_eq!(chord.notes()[1].velocity(), 0.5);
+                assert_eq!(chord.notes()[1].channel(), None);
+            }
+            SequenceElement::Note(_) => panic!("Expected a chord"),
+        }
+    }
+
+    fn notes(seq: &Sequence) -> Vec<(u32, u8, u32)> {
+        seq.elements()
+            .flat_map(|(time, element)| {
+                element
+                    .notes()
+                    .iter()
+                    .map(move |note| (time.ticks, note.note.midi_value(), note.duration.ticks))
+            })
+            .collect()
+    }
+
+    fn edited() -> Sequence {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_note(Time { ticks: 0 }, C, 0.5, quarter(96) * 2.0)
+            .unwrap();
+        seq.add_chord(
+            Time { ticks: 96 },
+            vec![(E, 0.5, quarter(96)), (G, 0.5, quarter(96) * 3.0)],
+        )
+        .unwrap();
+        seq.add_note(Time { ticks: 384 }, D, 0.5, quarter(96))
+            .unwrap();
+        seq
+    }
+
+    #[test]
+    fn test_split_at() {
+        let mut seq = edited();
+        seq.split_at(Time { ticks: 144 });
+        assert_eq!(
+            notes(&seq),
+            vec![
+                (0, 60, 144),
+                (96, 64, 48),
+                (96, 67, 48),
+                (144, 60, 48),
+                (144, 64, 48),
+                (144, 67, 240),
+                (384, 62, 96)
+            ]
+        );
+        // The chord tail stays a chord:
+        let (_, element) = seq.elements_in(Time { ticks: 144 }..).nth(1).unwrap();
+        assert!(matches!(element, SequenceElement::Chord(_)));
+    }
+
+    #[test]
+    fn test_crop() {
+        let mut seq = edited();
+        seq.add_automation_point(1, Time { ticks: 0 }, 0.0, Interpolation::Step)
+            .unwrap();
+        seq.add_automation_point(1, Time { ticks: 192 }, 1.0, Interpolation::Step)
+            .unwrap();
+        seq.add_automation_point(7, Time { ticks: 0 }, 0.0, Interpolation::Linear)
+            .unwrap();
+        seq.add_automation_point(7, Time { ticks: 384 }, 1.0, Interpolation::Step)
+            .unwrap();
+        seq.crop(Time { ticks: 96 }..Time { ticks: 288 }).unwrap();
+        assert_eq!(notes(&seq), vec![(96, 64, 96), (96, 67, 192), (96, 60, 96)]);
+        let points = |controller: u8| -> Vec<(u32, f32)> {
+            seq.automation(AutomationTarget::Controller(controller))
+                .unwrap()
+                .points()
+                .map(|(time, value, _)| (time.ticks, value))
+                .collect()
+        };
+        // The value set at tick 0 is kept from the start of the range:
+        assert_eq!(points(1), vec![(96, 0.0), (192, 1.0), (288, 1.0)]);
+        // The ramp keeps its values within the range:
+        assert_eq!(points(7), vec![(96, 0.25), (288, 0.75)]);
+        let lane = seq.automation(AutomationTarget::Controller(7)).unwrap();
+        assert_eq!(lane.value_at(Time { ticks: 192 }), Some(0.5));
+        assert!(seq.crop(Time { ticks: 96 }..Time { ticks: 0 }).is_err());
+    }
+
+    #[test]
+    fn test_insert_and_delete() {
+        let mut seq = edited();
+        seq.insert_time(Time { ticks: 96 }, quarter(96)).unwrap();
+        assert_eq!(
+            notes(&seq),
+            vec![
+                (0, 60, 96),
+                (192, 64, 96),
+                (192, 67, 288),
+                (192, 60, 96),
+                (480, 62, 96)
+            ]
+        );
+        seq.delete_range(Time { ticks: 96 }..Time { ticks: 192 })
+            .unwrap();
+        assert_eq!(
+            notes(&seq),
+            vec![
+                (0, 60, 96),
+                (96, 64, 96),
+                (96, 67, 288),
+                (96, 60, 96),
+                (384, 62, 96)
+            ]
+        );
+        // Deleting cuts the notes crossing the range, and the automation
+        // takes the value in force at the end of the range:
+        seq.add_automation_point(1, Time { ticks: 0 }, 0.0, Interpolation::Step)
+            .unwrap();
+        seq.add_automation_point(1, Time { ticks: 96 }, 1.0, Interpolation::Step)
+            .unwrap();
+        seq.delete_range(Time { ticks: 48 }..Time { ticks: 144 })
+            .unwrap();
+        let lane = seq.automation(AutomationTarget::Controller(1)).unwrap();
+        assert_eq!(
+            lane.points()
+                .map(|(time, value, _)| (time.ticks, value))
+                .collect::<Vec<_>>(),
+            vec![(0, 0.0), (47, 0.0), (48, 1.0)]
+        );
+        assert_eq!(
+            notes(&seq),
+            vec![
+                (0, 60, 48),
+                (48, 64, 48),
+                (48, 67, 240),
+                (48, 60, 48),
+                (288, 62, 96)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_insert_and_delete_automation_ramps() {
+        let ramp = || {
+            let mut seq = Sequence::new("test", common_time()).unwrap();
+            seq.add_automation_point(7, Time { ticks: 0 }, 0.0, Interpolation::Linear)
+                .unwrap();
+            seq.add_automation_point(7, Time { ticks: 200 }, 1.0, Interpolation::Linear)
+                .unwrap();
+            seq
+        };
+        let value_at = |seq: &Sequence, ticks| {
+            seq.automation(AutomationTarget::Controller(7))
+                .unwrap()
+                .value_at(Time { ticks })
+                .unwrap()
+        };
+
+        // The ramp is unchanged before the range, and carries on from
+        // its value at the end of the range:
+        let mut seq = ramp();
+        seq.delete_range(Time { ticks: 100 }..Time { ticks: 150 })
+            .unwrap();
+        assert_eq!(value_at(&seq, 50), 0.25);
+        assert_eq!(value_at(&seq, 99), 0.495);
+        assert_eq!(value_at(&seq, 100), 0.75);
+        assert_eq!(value_at(&seq, 150), 1.0);
+
+        // The ramp is unchanged before the silence, holds its value
+        // through it, and carries on after it:
+        let mut seq = ramp();
+        seq.insert_time(Time { ticks: 100 }, Time { ticks: 100 })
+            .unwrap();
+        assert_eq!(value_at(&seq, 50), 0.25);
+        assert_eq!(value_at(&seq, 100), 0.5);
+        assert_eq!(value_at(&seq, 199), 0.5);
+        assert_eq!(value_at(&seq, 250), 0.75);
+        assert_eq!(value_at(&seq, 300), 1.0);
+
+        // Lanes starting after the silence are only moved:
+        let mut seq = ramp();
+        seq.insert_time(Time { ticks: 0 }, Time { ticks: 100 })
+            .unwrap();
+        let lane = seq.automation(AutomationTarget::Controller(7)).unwrap();
+        assert_eq!(lane.value_at(Time { ticks: 50 }), None);
+        assert_eq!(value_at(&seq, 200), 0.5);
+    }
+
+    #[test]
+    fn test_end_past_the_largest_time() {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        seq.add_note(
+            Time {
+                ticks: u32::MAX - 10,
+            },
+            C,
+            0.5,
+            quarter(96),
+        )
+        .unwrap();
+        assert_eq!(seq.end_time(), Time { ticks: u32::MAX });
+        assert!(seq.insert_time(Time { ticks: 0 }, quarter(96)).is_err());
+        // The note can still be rendered with a groove:
+        seq.set_groove(Groove::swing(66.0, SwingGrid::Eighth).unwrap())
+            .unwrap();
+        assert_eq!(seq.channel_events().len(), 2);
+    }
+
+    #[test]
+    fn test_shift() {
+        let mut seq = edited();
+        seq.shift(TimeDelta::new(96)).unwrap();
+        assert_eq!(notes(&seq)[0], (96, 60, 192));
+        assert!(seq.shift(TimeDelta::new(-97)).is_err());
+        assert_eq!(notes(&seq)[0], (96, 60, 192));
+        seq.shift(TimeDelta::new(-96)).unwrap();
+        assert_eq!(notes(&seq), notes(&edited()));
+        assert!(seq.shift(TimeDelta::new(u32::MAX as i64)).is_err());
+    }
+
+    fn phrase(notes: &[(u32, Note)]) -> Sequence {
+        let mut seq = Sequence::new("test", common_time()).unwrap();
+        for (ticks, note) in notes {
+            seq.add_note(Time { ticks: *ticks }, *note, 0.5, quarter(96))
+                .unwrap();
+        }
+        seq
+    }
+
+    #[test]
+    fn test_concat() {
+        let first = phrase(&[(0, C), (96, E)]);
+        let second = phrase(&[(0, D)]);
+        let seq = first.concat(&second, Alignment::EndTime).unwrap();
+        assert_eq!(notes(&seq), vec![(0, 60, 96), (96, 64, 96), (192, 62, 96)]);
+        let seq = first.concat(&second, Alignment::NextBar).unwrap();
+        assert_eq!(notes(&seq)[2], (384, 62, 96));
+        let seq = (first.clone() + second.clone()).unwrap();
+        assert_eq!(notes(&seq)[2], (192, 62, 96));
+        let seq = (first.clone() + second.clone() + second).unwrap();
+        assert_eq!(notes(&seq)[3], (288, 62, 96));
+
+        // The second sequence is converted to the first resolution, and
+        // keeps its channel:
+        let mut second = Sequence::new("test", TimeSignature::new("4/4", 480).unwrap()).unwrap();
+        second.set_channel(Channel::new(1).unwrap());
+        second
+            .add_note(Time { ticks: 480 }, D, 0.5, quarter(480))
+            .unwrap();
+        second.set_program(Program::new(33).unwrap());
+        let seq = first.concat(&second, Alignment::EndTime).unwrap();
+        assert_eq!(seq.ticks_per_quarter_note(), 96);
+        assert_eq!(seq.program(), None);
+        assert_eq!(
+            seq.program_on_channel(Some(Channel::new(1).unwrap())),
+            Some(&Program::new(33).unwrap())
+        );
+        assert_eq!(notes(&seq)[2], (288, 62, 96));
+        let (_, appended) = seq.notes_at(Time { ticks: 288 })[0];
+        assert_eq!(appended.channel(), Some(Channel::new(1).unwrap()));
+    }
+
+    #[test]
+    fn test_overlay() {
+        let mut first = phrase(&[(0, C), (96, E)]);
+        first
+            .add_automation_point(1, Time { ticks: 0 }, 0.0, Interpolation::Linear)
+            .unwrap();
+        let mut second = phrase(&[(0, D)]);
+        second
+            .add_automation_point(1, Time { ticks: 96 }, 1.0, Interpolation::Step)
+            .unwrap();
+        let third = phrase(&[(96, G)]);
+        let seq = (first | second | third).unwrap();
+        assert_eq!(
+            notes(&seq),
+            vec![(0, 60, 96), (0, 62, 96), (96, 64, 96), (96, 67, 96)]
+        );
+        let lane = seq.automation(AutomationTarget::Controller(1)).unwrap();
+        assert_eq!(lane.points().count(), 2);
+        assert_eq!(lane.value_at(Time { ticks: 48 }), Some(0.5));
+    }
+
+    #[test]
+    fn test_overlay_automation_channels() {
+        let mut first = phrase(&[(0, C)]);
+        first.set_pitch_bend_range(12.0).unwrap();
+        first
+            .add_pitch_bend(Time { ticks: 0 }, 12.0, Interpolation::Step)
+            .unwrap();
+        let mut second = phrase(&[(0, D)]);
+        second.set_channel(Channel::new(1).unwrap());
+        second
+            .add_pitch_bend(Time { ticks: 0 }, 1.0, Interpolation::Step)
+            .unwrap();
+        second
+            .add_automation_point(1, Time { ticks: 0 }, 1.0, Interpolation::Step)
+            .unwrap();
+        let seq = first.overlay(&second).unwrap();
+        // Only the lanes of the first sequence are on the sequence
+        // channel:
+        assert_eq!(seq.automation(AutomationTarget::Controller(1)), None);
+        let channel = Some(Channel::new(1).unwrap());
+        assert!(seq
+            .automation_on_channel(channel, AutomationTarget::Controller(1))
+            .is_some());
+        assert_eq!(seq.pitch_bend_range_on_channel(channel), None);
+        let mut removed = seq.clone();
+        assert!(removed
+            .remove_automation_on_channel(channel, AutomationTarget::Controller(1))
+            .is_some());
+        assert_eq!(
+            removed.automation_on_channel(channel, AutomationTarget::Controller(1)),
+            None
+        );
+        let mut messages: Vec<(u8, MidiMessage)> = seq
+            .channel_events()
+            .into_iter()
+            .filter_map(|(_, kind)| match kind {
+                TrackEventKind::Midi { channel, message } => Some((channel.as_int(), message)),
+                _ => None,
+            })
+            .filter(|(_, message)| {
+                !matches!(
+                    message,
+                    MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. }
+                )
+            })
+            .collect();
+        // The range is only sent on the channel of the first sequence:
+        assert_eq!(
+            messages
+                .drain(..6)
+                .filter(|(channel, _)| *channel == 0)
+                .count(),
+            6
+        );
+        assert_eq!(
+            messages,
+            vec![
+                // Fully up over 12 semitones:
+                (
+                    0,
+                    MidiMessage::PitchBend {
+                        bend: PitchBend(16383.into())
+                    }
+                ),
+                // The second sequence keeps its channel:
+                (
+                    1,
+                    MidiMessage::Controller {
+                        controller: 1.into(),
+                        value: 127.into()
+                    }
+                ),
+                // One semitone is half of its default range:
+                (
+                    1,
+                    MidiMessage::PitchBend {
+                        bend: PitchBend(12287.into())
+                    }
+                ),
+            ]
+        );
+
+        // Different ranges on the same channel cannot be joined:
+        second.set_channel(Channel::default());
+        second.set_pitch_bend_range(2.0).unwrap();
+        assert!(first.overlay(&second).is_err());
+        // Neither sequence is changed by the error:
+        assert_eq!(second.pitch_bend_range(), Some(2.0));
+        assert_eq!(first.pitch_bend_range(), Some(12.0));
+        assert!((first.clone() | second.clone() | phrase(&[])).is_err());
+        // Without its range, the bends of the second sequence keep their
+        // semitones within the range of the first:
+        let mut second = phrase(&[(0, D)]);
+        second
+            .add_pitch_bend(Time { ticks: 96 }, -2.0, Interpolation::Step)
+            .unwrap();
+        let seq = (first | second).unwrap();
+        let lane = seq.automation(AutomationTarget::PitchBend).unwrap();
+        assert_eq!(lane.value_at(Time { ticks: 96 }), Some(-2.0));
+    }
+
+    #[test]
+    fn test_invalid_resolution() {
+        let seq = Sequence::new("test", common_time()).unwrap();
+        assert!(seq.clone().with_resolution(0).is_err());
+        assert!(seq.with_resolution(40_000).is_err());
     }
 }
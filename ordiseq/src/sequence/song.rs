@@ -0,0 +1,255 @@
+use crate::error::OrdiseqError;
+use crate::midi::{check_resolution, to_track};
+use crate::sequence::{DrumSequence, Sequence};
+use crate::time::{MeterMap, Tempo, TempoCurve, TempoMap, Time, TimeSignature};
+use midly::{Format, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+/// A part of a song: either a melodic sequence or a drum sequence.
+pub enum SongPart {
+    Sequence(Sequence),
+    Drums(DrumSequence),
+}
+
+impl SongPart {
+    fn channel_events(&self) -> Vec<(u32, TrackEventKind<'static>)> {
+        match self {
+            SongPart::Sequence(sequence) => sequence.channel_events(),
+            SongPart::Drums(drums) => drums.channel_events(),
+        }
+    }
+}
+
+/// A named track of a song.
+pub struct SongTrack {
+    pub name: String,
+    pub part: SongPart,
+}
+
+/// Represents a song made of several named tracks, sharing one tempo
+/// map and one meter map.
+///
+/// A song exports a multi-track (type 1) MIDI file: a conductor track
+/// with the tempo and time signature changes, followed by one track
+/// per part, so that DAWs import each part on its own lane. The tempo
+/// and time signatures of the song replace those of its parts.
+pub struct Song {
+    title: String,
+    meter_map: MeterMap,
+    tempo_map: TempoMap,
+    tracks: Vec<SongTrack>,
+}
+
+impl Song {
+    /// Creates a new empty song with the given time signature.
+    ///
+    /// The resolution of the song is the ticks per quarter note of the
+    /// time signature. Parts at a different resolution are converted
+    /// when they are added.
+    pub fn new(title: &str, time_signature: TimeSignature) -> Result<Self, OrdiseqError> {
+        check_resolution(time_signature.ticks_per_quarter_note())?;
+        time_signature.check()?;
+        Ok(Song {
+            title: title.to_string(),
+            meter_map: MeterMap::new(time_signature),
+            tempo_map: TempoMap::new(Tempo::default(), time_signature.ticks_per_quarter_note())?,
+            tracks: Vec::new(),
+        })
+    }
+
+    /// Sets the time signature starting at a specific bar (counting from 1).
+    pub fn set_time_signature(
+        &mut self,
+        bar: u32,
+        time_signature: TimeSignature,
+    ) -> Result<(), OrdiseqError> {
+        self.meter_map.set_time_signature(bar, time_signature)
+    }
+
+    /// Sets the length of the pickup (anacrusis) before the first
+    /// downbeat. Bar 1 starts after the pickup.
+    pub fn set_pickup(&mut self, length: Time) -> Result<(), OrdiseqError> {
+        self.meter_map.set_pickup(length)
+    }
+
+    /// Sets the tempo starting at a specific time.
+    pub fn set_tempo(&mut self, time: Time, tempo: Tempo) {
+        self.tempo_map.set_tempo(time, tempo);
+    }
+
+    /// Sets a gradual tempo change between two times.
+    pub fn set_tempo_ramp(
+        &mut self,
+        start: Time,
+        end: Time,
+        from: Tempo,
+        to: Tempo,
+        curve: TempoCurve,
+    ) -> Result<(), OrdiseqError> {
+        self.tempo_map.set_tempo_ramp(start, end, from, to, curve)
+    }
+
+    /// Adds a melodic sequence as a new track, converting it to the
+    /// resolution of the song if needed.
+    pub fn add_sequence(&mut self, name: &str, sequence: Sequence) -> Result<(), OrdiseqError> {
+        let sequence = match sequence.ticks_per_quarter_note() == self.ticks_per_quarter_note() {
+            true => sequence,
+            false => sequence.with_resolution(self.ticks_per_quarter_note())?,
+        };
+        self.add_track(name, SongPart::Sequence(sequence));
+        Ok(())
+    }
+
+    /// Adds a drum sequence as a new track, converting it to the
+    /// resolution of the song if needed.
+    pub fn add_drums(&mut self, name: &str, drums: DrumSequence) -> Result<(), OrdiseqError> {
+        let drums = match drums.ticks_per_quarter_note() == self.ticks_per_quarter_note() {
+            true => drums,
+            false => drums.with_resolution(self.ticks_per_quarter_note())?,
+        };
+        self.add_track(name, SongPart::Drums(drums));
+        Ok(())
+    }
+
+    fn add_track(&mut self, name: &str, part: SongPart) {
+        self.tracks.push(SongTrack {
+            name: name.to_string(),
+            part,
+        });
+    }
+
+    /// Returns the track with the given name.
+    pub fn track(&self, name: &str) -> Option<&SongTrack> {
+        self.tracks.iter().find(|track| track.name == name)
+    }
+
+    /// Removes the track with the given name, returning it.
+    pub fn remove_track(&mut self, name: &str) -> Option<SongTrack> {
+        let index = self.tracks.iter().position(|track| track.name == name)?;
+        Some(self.tracks.remove(index))
+    }
+
+    /// Returns all the tracks in the order they were added.
+    pub fn tracks(&self) -> &[SongTrack] {
+        &self.tracks
+    }
+
+    /// Converts the song into a multi-track MIDI `Smf`.
+    ///
+    /// The first track is the conductor track, named after the song,
+    /// with the tempo and time signature changes. Each part follows on
+    /// its own track, named after the part.
+    pub fn to_midi(&self) -> Smf<'_> {
+        let mut conductor = vec![(
+            0,
+            TrackEventKind::Meta(MetaMessage::TrackName(self.title.as_bytes())),
+        )];
+        conductor.extend(self.meter_map.midi_events());
+        conductor.extend(self.tempo_map.midi_events());
+
+        let mut tracks: Vec<Vec<TrackEvent<'_>>> = vec![to_track(conductor)];
+        for track in &self.tracks {
+            let mut events = vec![(
+                0,
+                TrackEventKind::Meta(MetaMessage::TrackName(track.name.as_bytes())),
+            )];
+            events.extend(track.part.channel_events());
+            tracks.push(to_track(events));
+        }
+
+        Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical((self.ticks_per_quarter_note() as u16).into()),
+            },
+            tracks,
+        }
+    }
+
+    /// Returns the resolution of the song, used for every track and for
+    /// the MIDI file header.
+    pub fn ticks_per_quarter_note(&self) -> u32 {
+        self.meter_map.ticks_per_quarter_note()
+    }
+
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+    pub fn meter_map(&self) -> &MeterMap {
+        &self.meter_map
+    }
+    pub fn tempo_map(&self) -> &TempoMap {
+        &self.tempo_map
+    }
+    pub fn tempo_map_mut(&mut self) -> &mut TempoMap {
+        &mut self.tempo_map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::common_time;
+    use klib::core::note::{C, E};
+
+    fn track_name<'a>(track: &'a [TrackEvent<'a>]) -> Option<&'a [u8]> {
+        track.iter().find_map(|event| match event.kind {
+            TrackEventKind::Meta(MetaMessage::TrackName(name)) => Some(name),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_to_midi() {
+        let mut song = Song::new("Song", common_time()).unwrap();
+        song.set_tempo(Time { ticks: 384 }, Tempo::from_bpm(90.0).unwrap());
+
+        let mut melody = Sequence::new("melody", common_time()).unwrap();
+        melody
+            .add_note(Time { ticks: 0 }, E, 0.5, Time { ticks: 96 })
+            .unwrap();
+        song.add_sequence("Melody", melody).unwrap();
+
+        let mut drums = DrumSequence::new("drums", common_time()).unwrap();
+        drums.add_euclidean_track("Kick", C, 4, 4, 0, 0.8).unwrap();
+        song.add_drums("Drums", drums).unwrap();
+
+        let smf = song.to_midi();
+        assert_eq!(smf.header.format, Format::Parallel);
+        assert_eq!(smf.tracks.len(), 3);
+        let names: Vec<_> = smf.tracks.iter().map(|track| track_name(track)).collect();
+        assert_eq!(
+            names,
+            vec![
+                Some(&b"Song"[..]),
+                Some(&b"Melody"[..]),
+                Some(&b"Drums"[..])
+            ]
+        );
+        // Only the conductor track has tempo and time signature events:
+        for (index, track) in smf.tracks.iter().enumerate() {
+            let has_tempo = track
+                .iter()
+                .any(|event| matches!(event.kind, TrackEventKind::Meta(MetaMessage::Tempo(_))));
+            assert_eq!(has_tempo, index == 0);
+        }
+        assert_eq!(smf.tracks[2].len(), 1 + 4 * 2 + 1);
+    }
+
+    #[test]
+    fn test_resolution_is_reconciled() {
+        let mut song = Song::new("Song", common_time()).unwrap();
+        let mut melody = Sequence::new("melody", TimeSignature::new("4/4", 480).unwrap()).unwrap();
+        melody
+            .add_note(Time { ticks: 480 }, E, 0.5, Time { ticks: 240 })
+            .unwrap();
+        song.add_sequence("Melody", melody).unwrap();
+
+        let smf = song.to_midi();
+        assert_eq!(smf.header.timing, Timing::Metrical(96.into()));
+        // The note on moved from tick 480 to tick 96:
+        assert_eq!(smf.tracks[1][1].delta.as_int(), 96);
+        assert!(song.track("Melody").is_some());
+        assert!(song.remove_track("Melody").is_some());
+        assert!(song.tracks().is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::OrdiseqError;
+use crate::time::Time;
+
+/// Represents a repeating loop of equal steps.
+///
+/// Parts with different cycles play against each other as a
+/// polymeter: a 5 beat cycle against a 4 beat cycle lines up again
+/// after 20 beats, the least common multiple of their lengths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycle {
+    steps: u32,
+    step: Time,
+}
+
+impl Cycle {
+    /// Creates a new cycle of `steps` steps, each `step` long.
+    pub fn new(steps: u32, step: Time) -> Result<Self, OrdiseqError> {
+        if steps == 0 || step.ticks == 0 {
+            return Err(OrdiseqError::InvalidCycle(format!(
+                "A cycle needs at least one step of at least one tick: {steps} x {}",
+                step.ticks
+            )));
+        }
+        steps.checked_mul(step.ticks).ok_or_else(|| {
+            OrdiseqError::InvalidCycle(format!("Cycle is too long: {steps} x {}", step.ticks))
+        })?;
+        Ok(Cycle { steps, step })
+    }
+
+    /// Returns the number of steps before the cycle repeats.
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// Returns the length of each step.
+    pub fn step(&self) -> Time {
+        self.step
+    }
+
+    /// Returns the length of one repetition of the cycle.
+    pub fn length(&self) -> Time {
+        Time {
+            ticks: self.steps * self.step.ticks,
+        }
+    }
+
+    /// Returns the start times of every step of the cycle, repeating
+    /// it until `length`, as (step index within the cycle, time).
+    pub fn step_times(&self, length: Time) -> impl Iterator<Item = (u32, Time)> + '_ {
+        (0..)
+            .map(move |index: u64| index * self.step.ticks as u64)
+            .take_while(move |ticks| *ticks < length.ticks as u64)
+            .enumerate()
+            .map(move |(index, ticks)| {
+                (
+                    (index as u64 % self.steps as u64) as u32,
+                    Time {
+                        ticks: ticks as u32,
+                    },
+                )
+            })
+    }
+
+    /// Returns the least common multiple of the lengths of the cycles:
+    /// the length after which all of them start together again.
+    ///
+    /// Returns an error if there are no cycles, or if the length does
+    /// not fit in a `Time`.
+    pub fn common_length<'a, I>(cycles: I) -> Result<Time, OrdiseqError>
+    where
+        I: IntoIterator<Item = &'a Cycle>,
+    {
+        let mut ticks: Option<u64> = None;
+        for cycle in cycles {
+            let length = cycle.length().ticks as u64;
+            let lcm = match ticks {
+                Some(ticks) => ticks / gcd(ticks, length) * length,
+                None => length,
+            };
+            if lcm > u32::MAX as u64 {
+                return Err(OrdiseqError::InvalidCycle(format!(
+                    "The common length of the cycles is too long: {lcm} ticks"
+                )));
+            }
+            ticks = Some(lcm);
+        }
+        let ticks = ticks.ok_or_else(|| OrdiseqError::InvalidCycle("No cycles".to_string()))?;
+        Ok(Time {
+            ticks: ticks as u32,
+        })
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beats(steps: u32) -> Cycle {
+        Cycle::new(steps, Time { ticks: 96 }).unwrap()
+    }
+
+    #[test]
+    fn test_common_length() {
+        assert_eq!(
+            Cycle::common_length(&[beats(5), beats(4)]).unwrap(),
+            Time { ticks: 20 * 96 }
+        );
+        assert_eq!(
+            Cycle::common_length(&[beats(4), beats(2), beats(8)]).unwrap(),
+            Time { ticks: 8 * 96 }
+        );
+        // Different step lengths:
+        let eighths = Cycle::new(3, Time { ticks: 48 }).unwrap();
+        assert_eq!(
+            Cycle::common_length(&[eighths, beats(1)]).unwrap(),
+            Time { ticks: 288 }
+        );
+        assert!(Cycle::common_length(&[]).is_err());
+    }
+
+    #[test]
+    fn test_step_times() {
+        let cycle = Cycle::new(3, Time { ticks: 10 }).unwrap();
+        let times: Vec<(u32, u32)> = cycle
+            .step_times(Time { ticks: 50 })
+            .map(|(index, time)| (index, time.ticks))
+            .collect();
+        assert_eq!(times, vec![(0, 0), (1, 10), (2, 20), (0, 30), (1, 40)]);
+    }
+
+    #[test]
+    fn test_invalid_cycle() {
+        assert!(Cycle::new(0, Time { ticks: 96 }).is_err());
+        assert!(Cycle::new(4, Time { ticks: 0 }).is_err());
+        assert!(Cycle::new(u32::MAX, Time { ticks: 2 }).is_err());
+        let cycle = Cycle::new(4, Time { ticks: 96 }).unwrap();
+        assert_eq!(cycle.steps(), 4);
+        assert_eq!(cycle.step(), Time { ticks: 96 });
+    }
+}
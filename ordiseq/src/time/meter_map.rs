@@ -0,0 +1,497 @@
+use crate::error::OrdiseqError;
+use crate::time::{Time, TimeSignature};
+use midly::TrackEventKind;
+use std::collections::BTreeMap;
+
+/// Represents the time signature changes of a sequence.
+///
+/// Time signatures change at bar boundaries. Bars are numbered
+/// starting from 1, and a `MeterMap` always has a time signature for
+/// bar 1. All the time signatures must share the same ticks per
+/// quarter note.
+///
+/// A sequence may start with a pickup (anacrusis): a partial bar 0
+/// before the first downbeat. Bar 0 has the time signature of bar 1,
+/// and its beats are counted as the last beats of a full bar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeterMap {
+    changes: BTreeMap<u32, TimeSignature>,
+    pickup: Time,
+}
+
+impl MeterMap {
+    /// Creates a new meter map starting with the given time signature.
+    pub fn new(time_signature: TimeSignature) -> Self {
+        let mut changes = BTreeMap::new();
+        changes.insert(1, time_signature);
+        MeterMap {
+            changes,
+            pickup: Time { ticks: 0 },
+        }
+    }
+
+    /// Sets the length of the pickup bar (bar 0), so that bar 1 starts
+    /// after it. A length of zero removes the pickup.
+    ///
+    /// Returns an error if the pickup is not shorter than bar 1.
+    pub fn set_pickup(&mut self, length: Time) -> Result<(), OrdiseqError> {
+        let bar_time = self.time_signature_at_bar(1).bar_time();
+        if length >= bar_time {
+            return Err(OrdiseqError::InvalidTimeSignature(format!(
+                "A pickup must be shorter than a bar: {} >= {} ticks",
+                length.ticks, bar_time.ticks
+            )));
+        }
+        self.pickup = length;
+        Ok(())
+    }
+
+    /// Returns the length of the pickup bar, zero if there is none.
+    pub fn pickup(&self) -> Time {
+        self.pickup
+    }
+
+    /// Sets the time signature starting at a specific bar, replacing
+    /// any previous change at the same bar.
+    pub fn set_time_signature(
+        &mut self,
+        bar: u32,
+        time_signature: TimeSignature,
+    ) -> Result<(), OrdiseqError> {
+        if bar == 0 {
+            return Err(OrdiseqError::InvalidTimeSignature(
+                "Time signatures change at bar 1 or later, bar 0 is the pickup".to_string(),
+            ));
+        }
+        time_signature.check()?;
+        if time_signature.ticks_per_quarter_note() != self.ticks_per_quarter_note() {
+            return Err(OrdiseqError::InvalidTimeSignature(format!(
+                "Ticks per quarter note must match the sequence: {} != {}",
+                time_signature.ticks_per_quarter_note(),
+                self.ticks_per_quarter_note()
+            )));
+        }
+        if bar == 1 && self.pickup >= time_signature.bar_time() {
+            return Err(OrdiseqError::InvalidTimeSignature(format!(
+                "The pickup must be shorter than bar 1: {} >= {} ticks",
+                self.pickup.ticks,
+                time_signature.bar_time().ticks
+            )));
+        }
+        let previous = self.changes.insert(bar, time_signature);
+        if let Some(late) = self
+            .changes
+            .keys()
+            .find(|bar| self.checked_bar_start(**bar).is_none())
+            .copied()
+        {
+            match previous {
+                Some(previous) => self.changes.insert(bar, previous),
+                None => self.changes.remove(&bar),
+            };
+            return Err(OrdiseqError::InvalidTimeSignature(format!(
+                "Bar {late} would start past the largest time"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Removes the time signature change at a specific bar.
+    ///
+    /// The time signature of bar 1 cannot be removed, use
+    /// `set_time_signature` to replace it instead.
+    pub fn remove_time_signature(&mut self, bar: u32) -> Option<TimeSignature> {
+        if bar <= 1 {
+            return None;
+        }
+        self.changes.remove(&bar)
+    }
+
+    /// Returns the time signature in force at a specific bar.
+    pub fn time_signature_at_bar(&self, bar: u32) -> TimeSignature {
+        self.changes
+            .range(..=bar.max(1))
+            .next_back()
+            .map(|(_, time_signature)| *time_signature)
+            .expect("A meter map always has a time signature for bar 1")
+    }
+
+    /// Returns the time signature in force at a specific time.
+    pub fn time_signature_at(&self, time: Time) -> TimeSignature {
+        self.time_signature_at_bar(self.bar_at(time))
+    }
+
+    /// Returns the time at which a bar starts. Bar 0, the pickup,
+    /// starts at zero.
+    ///
+    /// Panics if the bar starts past the largest time; use
+    /// `checked_bar_start` to handle that case.
+    pub fn bar_start(&self, bar: u32) -> Time {
+        self.checked_bar_start(bar)
+            .expect("The bar starts past the largest time")
+    }
+
+    /// Returns the time at which a bar starts, or `None` if it starts
+    /// past the largest time.
+    pub fn checked_bar_start(&self, bar: u32) -> Option<Time> {
+        u32::try_from(self.bar_start_ticks(bar))
+            .ok()
+            .map(|ticks| Time { ticks })
+    }
+
+    /// Returns the number of ticks from the start of the sequence to a
+    /// bar, which may be out of the range of `Time`.
+    pub(crate) fn bar_start_ticks(&self, bar: u32) -> u64 {
+        if bar == 0 {
+            return 0;
+        }
+        let mut ticks = self.pickup.ticks as u64;
+        for (start_bar, end_bar, time_signature) in self.segments() {
+            if bar <= start_bar {
+                break;
+            }
+            let end_bar = end_bar.map_or(bar, |end_bar| end_bar.min(bar));
+            ticks += (end_bar - start_bar) as u64 * time_signature.bar_time().ticks as u64;
+        }
+        ticks
+    }
+
+    /// Returns the number of the bar that contains a specific time.
+    /// Times within the pickup are in bar 0.
+    pub fn bar_at(&self, time: Time) -> u32 {
+        if time < self.pickup {
+            return 0;
+        }
+        let mut start = self.pickup;
+        for (start_bar, end_bar, time_signature) in self.segments() {
+            let bar_ticks = time_signature.bar_time().ticks;
+            if let Some(end_bar) = end_bar {
+                let end = start + (end_bar - start_bar) * bar_ticks;
+                if time >= end {
+                    start = end;
+                    continue;
+                }
+            }
+            return start_bar + (time.ticks - start.ticks) / bar_ticks;
+        }
+        unreachable!("A meter map always has an open ended last segment")
+    }
+
+    /// Returns true if a time falls exactly on the first beat of a bar.
+    /// The start of a pickup is not a downbeat.
+    pub fn is_downbeat(&self, time: Time) -> bool {
+        self.bar_offset(time).ticks == 0
+    }
+
+    /// Returns the offset of a time from the start of its bar.
+    ///
+    /// Within the pickup, the offset is counted as if the pickup were
+    /// the end of a full bar, so that its beats line up with the beats
+    /// of bar 1.
+    pub fn bar_offset(&self, time: Time) -> Time {
+        let bar = self.bar_at(time);
+        if bar == 0 {
+            let bar_time = self.time_signature_at_bar(1).bar_time();
+            return Time {
+                ticks: bar_time.ticks - (self.pickup.ticks - time.ticks),
+            };
+        }
+        Time {
+            ticks: time.ticks - self.bar_start(bar).ticks,
+        }
+    }
+
+    /// Returns true if a time falls exactly on a strong beat: the first
+    /// beat of a group of the time signature in force.
+    pub fn is_strong_beat(&self, time: Time) -> bool {
+        self.time_signature_at(time)
+            .strong_beat_times()
+            .contains(&self.bar_offset(time))
+    }
+
+    /// Returns the metric weight of a time, following the grouping of
+    /// the time signature in force.
+    pub fn metric_weight(&self, time: Time) -> f32 {
+        self.time_signature_at(time)
+            .metric_weight(self.bar_offset(time))
+    }
+
+    /// Returns all the time signature changes in order, as (bar, start
+    /// time, time signature).
+    pub fn changes(&self) -> impl Iterator<Item = (u32, Time, TimeSignature)> + '_ {
+        self.changes
+            .iter()
+            .map(|(bar, time_signature)| (*bar, self.bar_start(*bar), *time_signature))
+    }
+
+    pub fn ticks_per_quarter_note(&self) -> u32 {
+        self.time_signature_at_bar(1).ticks_per_quarter_note()
+    }
+
+    /// Returns a copy of the meter map with every time signature set to
+    /// a different resolution.
+    ///
+    /// Returns an error if a beat of one of the time signatures would
+    /// be shorter than a tick at the new resolution.
+    pub fn with_resolution(&self, ticks_per_quarter_note: u32) -> Result<Self, OrdiseqError> {
+        let mut changes = BTreeMap::new();
+        for (bar, time_signature) in &self.changes {
+            changes.insert(
+                *bar,
+                time_signature.with_resolution(ticks_per_quarter_note)?,
+            );
+        }
+        Ok(MeterMap {
+            changes,
+            pickup: self
+                .pickup
+                .rescale(self.ticks_per_quarter_note(), ticks_per_quarter_note),
+        })
+    }
+
+    /// Returns the `TimeSignature` meta events for all the changes, as
+    /// (absolute ticks, event) pairs.
+    ///
+    /// A pickup is written as a bar of its own, in the shortest time
+    /// signature that fits it exactly (one beat of a 1/4 bar for a
+    /// quarter note pickup in 4/4), so the following bars line up with
+    /// the downbeat.
+    pub(crate) fn midi_events(&self) -> Vec<(u32, TrackEventKind<'static>)> {
+        let mut events = Vec::new();
+        if self.pickup.ticks > 0 {
+            let time_signature = self.pickup_time_signature();
+            events.push((0, TrackEventKind::Meta(time_signature.to_midi_meta())));
+        }
+        events.extend(self.changes().map(|(_, time, time_signature)| {
+            (
+                time.ticks,
+                TrackEventKind::Meta(time_signature.to_midi_meta()),
+            )
+        }));
+        events
+    }
+
+    /// Returns a time signature as long as the pickup, using the beat
+    /// unit of bar 1 or the smallest shorter unit that divides it. If
+    /// no unit fits, the time signature of bar 1 is used.
+    fn pickup_time_signature(&self) -> TimeSignature {
+        let bar_one = self.time_signature_at_bar(1);
+        let mut beat_unit = bar_one.beat_unit().max(1);
+        loop {
+            let ticks_per_quarter_note = bar_one.ticks_per_quarter_note();
+            let ticks_per_beat =
+                TimeSignature::plain(1, beat_unit, ticks_per_quarter_note).ticks_per_beat();
+            if ticks_per_beat > 0 && self.pickup.ticks.is_multiple_of(ticks_per_beat) {
+                let beats = self.pickup.ticks / ticks_per_beat;
+                if let Ok(beats_per_bar) = u8::try_from(beats) {
+                    return TimeSignature::plain(beats_per_bar, beat_unit, ticks_per_quarter_note);
+                }
+            }
+            match beat_unit.checked_mul(2) {
+                Some(unit) if unit <= 64 => beat_unit = unit,
+                _ => return bar_one,
+            }
+        }
+    }
+
+    /// Iterates over (start bar, end bar, time signature) segments,
+    /// where the last segment has no end.
+    fn segments(&self) -> impl Iterator<Item = (u32, Option<u32>, TimeSignature)> + '_ {
+        let mut iter = self.changes.iter().peekable();
+        std::iter::from_fn(move || {
+            let (start, time_signature) = iter.next()?;
+            let end = iter.peek().map(|(end, _)| **end);
+            Some((*start, end, *time_signature))
+        })
+    }
+}
+
+impl From<TimeSignature> for MeterMap {
+    fn from(time_signature: TimeSignature) -> Self {
+        MeterMap::new(time_signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::MetaMessage;
+
+    fn four_four_then_seven_eight() -> MeterMap {
+        let mut map = MeterMap::new(TimeSignature::new("4/4", 96).unwrap());
+        map.set_time_signature(3, TimeSignature::new("7/8", 96).unwrap())
+            .unwrap();
+        map
+    }
+
+    #[test]
+    fn test_bar_start() {
+        let mut map = four_four_then_seven_eight();
+        assert_eq!(map.bar_start(1), Time { ticks: 0 });
+        assert_eq!(map.bar_start(2), Time { ticks: 384 });
+        assert_eq!(map.bar_start(3), Time { ticks: 768 });
+        assert_eq!(map.bar_start(4), Time { ticks: 768 + 336 });
+        assert_eq!(map.checked_bar_start(u32::MAX), None);
+        assert!(map
+            .set_time_signature(u32::MAX, TimeSignature::new("3/4", 96).unwrap())
+            .is_err());
+        assert_eq!(map.changes().count(), 2);
+    }
+
+    #[test]
+    fn test_bar_at() {
+        let map = four_four_then_seven_eight();
+        assert_eq!(map.bar_at(Time { ticks: 0 }), 1);
+        assert_eq!(map.bar_at(Time { ticks: 767 }), 2);
+        assert_eq!(map.bar_at(Time { ticks: 768 }), 3);
+        assert_eq!(map.bar_at(Time { ticks: 768 + 336 }), 4);
+    }
+
+    #[test]
+    fn test_is_downbeat() {
+        let map = four_four_then_seven_eight();
+        assert!(map.is_downbeat(Time { ticks: 0 }));
+        assert!(map.is_downbeat(Time { ticks: 768 }));
+        assert!(!map.is_downbeat(Time { ticks: 96 }));
+    }
+
+    #[test]
+    fn test_strong_beats() {
+        let mut map = MeterMap::new(TimeSignature::new("4/4", 96).unwrap());
+        map.set_time_signature(2, TimeSignature::new("2+2+3/8", 96).unwrap())
+            .unwrap();
+        // Beats 1 and 3 of 4/4:
+        assert!(!map.is_strong_beat(Time { ticks: 96 }));
+        assert!(map.is_strong_beat(Time { ticks: 192 }));
+        assert!(map.is_strong_beat(Time { ticks: 384 + 96 }));
+        assert!(!map.is_strong_beat(Time { ticks: 384 + 48 }));
+        assert!(map.is_strong_beat(Time { ticks: 384 + 192 }));
+        assert_eq!(map.metric_weight(Time { ticks: 384 }), 1.0);
+        assert_eq!(map.metric_weight(Time { ticks: 384 + 240 }), 0.5);
+    }
+
+    fn pickup() -> MeterMap {
+        // One beat of pickup before 4/4, then 3/4 from bar 2:
+        let mut map = MeterMap::new(TimeSignature::new("4/4", 96).unwrap());
+        map.set_pickup(Time { ticks: 96 }).unwrap();
+        map.set_time_signature(2, TimeSignature::new("3/4", 96).unwrap())
+            .unwrap();
+        map
+    }
+
+    #[test]
+    fn test_pickup() {
+        let map = pickup();
+        assert_eq!(map.bar_start(0), Time { ticks: 0 });
+        assert_eq!(map.bar_start(1), Time { ticks: 96 });
+        assert_eq!(map.bar_start(2), Time { ticks: 480 });
+        assert_eq!(map.bar_start(3), Time { ticks: 768 });
+        assert_eq!(map.bar_at(Time { ticks: 95 }), 0);
+        assert_eq!(map.bar_at(Time { ticks: 96 }), 1);
+        assert_eq!(map.bar_at(Time { ticks: 480 }), 2);
+        assert!(!map.is_downbeat(Time { ticks: 0 }));
+        assert!(map.is_downbeat(Time { ticks: 96 }));
+        // The pickup is the last beat of a 4/4 bar:
+        assert_eq!(map.bar_offset(Time { ticks: 0 }), Time { ticks: 288 });
+        assert_eq!(map.time_signature_at(Time { ticks: 0 }).to_string(), "4/4");
+    }
+
+    #[test]
+    fn test_invalid_pickup() {
+        let mut map = MeterMap::new(TimeSignature::new("3/4", 96).unwrap());
+        assert!(map.set_pickup(Time { ticks: 288 }).is_err());
+        assert!(map.set_pickup(Time { ticks: 287 }).is_ok());
+        assert!(map
+            .set_time_signature(1, TimeSignature::new("2/4", 96).unwrap())
+            .is_err());
+        assert!(map.set_pickup(Time { ticks: 0 }).is_ok());
+        assert!(map.is_downbeat(Time { ticks: 0 }));
+    }
+
+    #[test]
+    fn test_pickup_midi_events() {
+        let map = pickup();
+        assert_eq!(
+            map.midi_events(),
+            vec![
+                (
+                    0,
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(1, 2, 24, 8))
+                ),
+                (
+                    96,
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(4, 2, 24, 8))
+                ),
+                (
+                    480,
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(3, 2, 24, 8))
+                ),
+            ]
+        );
+        // An eighth note pickup is written as 1/8:
+        let mut map = MeterMap::new(TimeSignature::new("4/4", 96).unwrap());
+        map.set_pickup(Time { ticks: 48 }).unwrap();
+        assert_eq!(
+            map.midi_events()[0],
+            (
+                0,
+                TrackEventKind::Meta(MetaMessage::TimeSignature(1, 3, 24, 8))
+            )
+        );
+    }
+
+    #[test]
+    fn test_time_signature_at() {
+        let map = four_four_then_seven_eight();
+        assert_eq!(
+            map.time_signature_at(Time { ticks: 767 }).to_string(),
+            "4/4"
+        );
+        assert_eq!(
+            map.time_signature_at(Time { ticks: 768 }).to_string(),
+            "7/8"
+        );
+        assert_eq!(
+            map.time_signature_at(Time { ticks: 5000 }).to_string(),
+            "7/8"
+        );
+    }
+
+    #[test]
+    fn test_invalid_changes() {
+        let mut map = MeterMap::new(TimeSignature::new("4/4", 96).unwrap());
+        assert!(map
+            .set_time_signature(0, TimeSignature::new("3/4", 96).unwrap())
+            .is_err());
+        assert!(map
+            .set_time_signature(2, TimeSignature::new("3/4", 480).unwrap())
+            .is_err());
+        assert_eq!(map.remove_time_signature(1), None);
+    }
+
+    #[test]
+    fn test_with_resolution() {
+        let map = four_four_then_seven_eight().with_resolution(480).unwrap();
+        assert_eq!(map.bar_start(3), Time { ticks: 3840 });
+        // An eighth note is half a tick at 1 tick per quarter note:
+        assert!(four_four_then_seven_eight().with_resolution(1).is_err());
+    }
+
+    #[test]
+    fn test_midi_events() {
+        let map = four_four_then_seven_eight();
+        assert_eq!(
+            map.midi_events(),
+            vec![
+                (
+                    0,
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(4, 2, 24, 8))
+                ),
+                (
+                    768,
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(7, 3, 24, 8))
+                ),
+            ]
+        );
+    }
+}
@@ -1,6 +1,21 @@
 //! # Time
+mod cycle;
+mod meter_map;
+mod note_value;
+mod position;
+mod smpte;
+mod tempo_map;
+#[allow(clippy::module_inception)]
 mod time;
+mod time_delta;
 mod time_signature;
 
+pub use cycle::*;
+pub use meter_map::*;
+pub use note_value::*;
+pub use position::*;
+pub use smpte::*;
+pub use tempo_map::*;
 pub use time::*;
+pub use time_delta::*;
 pub use time_signature::*;
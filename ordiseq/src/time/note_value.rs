@@ -0,0 +1,237 @@
+use crate::error::OrdiseqError;
+use crate::time::Time;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The plain (undotted) note divisions, from whole notes to 64th notes.
+///
+/// The discriminant is the denominator of the note value (e.g. 4 for
+/// a quarter note).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Division {
+    Whole = 1,
+    Half = 2,
+    Quarter = 4,
+    Eighth = 8,
+    Sixteenth = 16,
+    ThirtySecond = 32,
+    SixtyFourth = 64,
+}
+
+/// Represents an n:m tuplet: `notes` notes played in the time of
+/// `in_time_of` notes of the same value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Tuplet {
+    pub notes: u32,
+    pub in_time_of: u32,
+}
+
+/// Represents the length of a note as written in a score.
+///
+/// A `NoteValue` is a `Division` which may be dotted any number of
+/// times and may be part of a tuplet. Conversion to ticks uses exact
+/// integer arithmetic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NoteValue {
+    pub division: Division,
+    pub dots: u8,
+    pub tuplet: Option<Tuplet>,
+}
+
+impl NoteValue {
+    pub fn new(division: Division) -> Self {
+        NoteValue {
+            division,
+            dots: 0,
+            tuplet: None,
+        }
+    }
+
+    /// Adds a dot to the note value, extending it by half of its
+    /// last part.
+    pub fn dotted(mut self) -> Self {
+        self.dots += 1;
+        self
+    }
+
+    /// Adds two dots to the note value.
+    pub fn double_dotted(self) -> Self {
+        self.dotted().dotted()
+    }
+
+    /// Makes the note value part of an n:m tuplet.
+    pub fn tuplet(mut self, notes: u32, in_time_of: u32) -> Self {
+        self.tuplet = Some(Tuplet { notes, in_time_of });
+        self
+    }
+
+    /// Makes the note value part of a 3:2 triplet.
+    pub fn triplet(self) -> Self {
+        self.tuplet(3, 2)
+    }
+
+    /// Converts the note value into a length in ticks.
+    ///
+    /// Returns an error if the length is not a whole number of ticks
+    /// at the given resolution.
+    pub fn to_ticks(&self, ticks_per_quarter_note: u32) -> Result<Time, OrdiseqError> {
+        let (tuplet_notes, in_time_of) = match self.tuplet {
+            Some(Tuplet { notes, in_time_of }) if notes > 0 && in_time_of > 0 => {
+                (notes as u128, in_time_of as u128)
+            }
+            Some(_) => {
+                return Err(OrdiseqError::InvalidNoteValue(format!(
+                    "Tuplet ratio must be positive: {self}"
+                )))
+            }
+            None => (1, 1),
+        };
+        if self.dots > 32 {
+            return Err(OrdiseqError::InvalidNoteValue(format!(
+                "Too many dots: {self}"
+            )));
+        }
+
+        // A note with `d` dots lasts (2^(d+1) - 1) / 2^d of the plain note.
+        let dot_denominator = 1u128 << self.dots;
+        let dot_numerator = (dot_denominator << 1) - 1;
+
+        let numerator = ticks_per_quarter_note as u128 * 4 * dot_numerator * in_time_of;
+        let denominator = self.division as u128 * dot_denominator * tuplet_notes;
+
+        if !numerator.is_multiple_of(denominator) {
+            return Err(OrdiseqError::InexactNoteValue {
+                note_value: self.to_string(),
+                ticks_per_quarter_note,
+            });
+        }
+        let ticks = u32::try_from(numerator / denominator).map_err(|_| {
+            OrdiseqError::InvalidNoteValue(format!("Note value is too long: {self}"))
+        })?;
+        Ok(Time { ticks })
+    }
+}
+
+impl From<Division> for NoteValue {
+    fn from(division: Division) -> Self {
+        NoteValue::new(division)
+    }
+}
+
+impl fmt::Display for NoteValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "1/{}", self.division as u32)?;
+        for _ in 0..self.dots {
+            write!(f, ".")?;
+        }
+        if let Some(tuplet) = self.tuplet {
+            write!(f, " {}:{}", tuplet.notes, tuplet.in_time_of)?;
+        }
+        Ok(())
+    }
+}
+
+/// A length that can be converted into ticks.
+///
+/// Plain numbers are counted in quarter notes.
+pub trait IntoTicks {
+    fn into_ticks(self, ticks_per_quarter_note: u32) -> Result<Time, OrdiseqError>;
+}
+
+impl IntoTicks for u32 {
+    fn into_ticks(self, ticks_per_quarter_note: u32) -> Result<Time, OrdiseqError> {
+        let ticks = self.checked_mul(ticks_per_quarter_note).ok_or_else(|| {
+            OrdiseqError::InvalidNoteValue(format!("{self} quarter notes is too long"))
+        })?;
+        Ok(Time { ticks })
+    }
+}
+
+impl IntoTicks for Time {
+    fn into_ticks(self, _ticks_per_quarter_note: u32) -> Result<Time, OrdiseqError> {
+        Ok(self)
+    }
+}
+
+impl IntoTicks for NoteValue {
+    fn into_ticks(self, ticks_per_quarter_note: u32) -> Result<Time, OrdiseqError> {
+        self.to_ticks(ticks_per_quarter_note)
+    }
+}
+
+impl IntoTicks for Division {
+    fn into_ticks(self, ticks_per_quarter_note: u32) -> Result<Time, OrdiseqError> {
+        NoteValue::new(self).to_ticks(ticks_per_quarter_note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks(note_value: NoteValue, ticks_per_quarter_note: u32) -> u32 {
+        note_value.to_ticks(ticks_per_quarter_note).unwrap().ticks
+    }
+
+    #[test]
+    fn test_plain_divisions() {
+        assert_eq!(ticks(Division::Whole.into(), 96), 384);
+        assert_eq!(ticks(Division::Quarter.into(), 96), 96);
+        assert_eq!(ticks(Division::Sixteenth.into(), 96), 24);
+        assert_eq!(ticks(Division::SixtyFourth.into(), 96), 6);
+    }
+
+    #[test]
+    fn test_dotted() {
+        assert_eq!(ticks(NoteValue::new(Division::Quarter).dotted(), 96), 144);
+        assert_eq!(ticks(NoteValue::new(Division::Sixteenth).dotted(), 96), 36);
+        assert_eq!(
+            ticks(NoteValue::new(Division::Quarter).double_dotted(), 96),
+            168
+        );
+    }
+
+    #[test]
+    fn test_tuplets() {
+        assert_eq!(ticks(NoteValue::new(Division::Eighth).triplet(), 96), 32);
+        assert_eq!(
+            ticks(NoteValue::new(Division::Sixteenth).tuplet(5, 4), 480),
+            96
+        );
+    }
+
+    #[test]
+    fn test_inexact() {
+        let result = NoteValue::new(Division::Sixteenth)
+            .tuplet(5, 4)
+            .to_ticks(96);
+        assert_eq!(
+            format!("{}", result.unwrap_err()),
+            "Note value 1/16 5:4 cannot be represented exactly at 96 ticks per quarter note"
+        );
+        assert!(NoteValue::new(Division::SixtyFourth)
+            .double_dotted()
+            .to_ticks(96)
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_tuplet() {
+        assert!(NoteValue::new(Division::Eighth)
+            .tuplet(0, 2)
+            .to_ticks(96)
+            .is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let value = NoteValue::new(Division::Eighth).double_dotted().triplet();
+        assert_eq!(value.to_string(), "1/8.. 3:2");
+    }
+
+    #[test]
+    fn test_quarter_notes_into_ticks() {
+        assert_eq!(3.into_ticks(96).unwrap(), Time { ticks: 288 });
+        assert!(5_000_000.into_ticks(960).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use crate::error::OrdiseqError;
+use crate::time::{MeterMap, Time};
+use std::fmt;
+use std::str::FromStr;
+
+/// Represents a musical position as bars, beats and ticks.
+///
+/// Bars and beats are counted from 1, and ticks are counted from 0
+/// within the beat. The length of a beat is given by the `beat_unit`
+/// of the time signature in force for the bar.
+///
+/// Bar 0 is the pickup. Its beats are numbered as the last beats of a
+/// full bar, so a one beat pickup in 4/4 is at 0.4.0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+impl Position {
+    pub fn new(bar: u32, beat: u32, tick: u32) -> Self {
+        Position { bar, beat, tick }
+    }
+
+    /// Converts the position into a time in ticks.
+    ///
+    /// Beats and ticks past the end of the bar carry over into the
+    /// following bars. A position in bar 0 before the start of the
+    /// pickup is clamped to tick 0, and a position past the largest
+    /// time is clamped to it: use `checked_to_time` to get an error
+    /// instead.
+    pub fn to_time(&self, meter_map: &MeterMap) -> Time {
+        Time {
+            ticks: self.ticks(meter_map).clamp(0, u32::MAX as i64) as u32,
+        }
+    }
+
+    /// Converts the position into a time in ticks, or returns an error
+    /// if it is before the start of the pickup or past the largest
+    /// time.
+    pub fn checked_to_time(&self, meter_map: &MeterMap) -> Result<Time, OrdiseqError> {
+        let ticks = self.ticks(meter_map);
+        u32::try_from(ticks)
+            .map(|ticks| Time { ticks })
+            .map_err(|_| {
+                OrdiseqError::InvalidPosition(match ticks < 0 {
+                    true => format!("{self} is {} ticks before the start", -ticks),
+                    false => format!("{self} is past the largest time"),
+                })
+            })
+    }
+
+    /// Returns the number of ticks from the start of the sequence to
+    /// the position, which may be out of the range of `Time`.
+    fn ticks(&self, meter_map: &MeterMap) -> i64 {
+        let time_signature = meter_map.time_signature_at_bar(self.bar);
+        let offset = self.beat.saturating_sub(1) as i64 * time_signature.ticks_per_beat() as i64
+            + self.tick as i64;
+        if self.bar == 0 {
+            // Count back from the downbeat of bar 1:
+            let before_downbeat = time_signature.bar_time().ticks as i64 - offset;
+            return meter_map.bar_start_ticks(1) as i64 - before_downbeat;
+        }
+        meter_map.bar_start_ticks(self.bar) as i64 + offset
+    }
+
+    /// Converts a time in ticks into a position.
+    pub fn from_time(time: Time, meter_map: &MeterMap) -> Self {
+        let bar = meter_map.bar_at(time);
+        let ticks_per_beat = meter_map.time_signature_at_bar(bar).ticks_per_beat();
+        let offset = meter_map.bar_offset(time).ticks;
+        Position {
+            bar,
+            beat: offset / ticks_per_beat + 1,
+            tick: offset % ticks_per_beat,
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.bar, self.beat, self.tick)
+    }
+}
+
+impl FromStr for Position {
+    type Err = OrdiseqError;
+
+    /// Parses a position in "bar.beat.tick" format. The beat and tick
+    /// may be left out, so "12" and "12.3" are also valid.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim().split('.').collect();
+        if parts.len() > 3 {
+            return Err(OrdiseqError::InvalidPosition(
+                "Input must be in the format 'bar.beat.tick'".to_string(),
+            ));
+        }
+
+        let parse = |index: usize, name: &str, default: u32| -> Result<u32, OrdiseqError> {
+            match parts.get(index) {
+                Some(part) => part.parse().map_err(|_| {
+                    OrdiseqError::InvalidPosition(format!("{name} must be a valid number"))
+                }),
+                None => Ok(default),
+            }
+        };
+        let bar = parse(0, "Bar", 1)?;
+        let beat = parse(1, "Beat", 1)?;
+        let tick = parse(2, "Tick", 0)?;
+
+        if beat == 0 {
+            return Err(OrdiseqError::InvalidPosition(
+                "Beats are counted from 1".to_string(),
+            ));
+        }
+
+        Ok(Position { bar, beat, tick })
+    }
+}
+
+/// A value that can be resolved to a `Time` within a sequence.
+pub trait IntoTime {
+    /// Resolves the value, or returns an error if it is not a valid
+    /// time within the sequence.
+    fn into_time(self, meter_map: &MeterMap) -> Result<Time, OrdiseqError>;
+}
+
+impl IntoTime for Time {
+    fn into_time(self, _meter_map: &MeterMap) -> Result<Time, OrdiseqError> {
+        Ok(self)
+    }
+}
+
+impl IntoTime for Position {
+    fn into_time(self, meter_map: &MeterMap) -> Result<Time, OrdiseqError> {
+        self.checked_to_time(meter_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::TimeSignature;
+
+    fn meter_map() -> MeterMap {
+        let mut map = MeterMap::new(TimeSignature::new("4/4", 96).unwrap());
+        map.set_time_signature(3, TimeSignature::new("7/8", 96).unwrap())
+            .unwrap();
+        map
+    }
+
+    #[test]
+    fn test_to_time() {
+        let map = meter_map();
+        assert_eq!(Position::new(1, 1, 0).to_time(&map), Time { ticks: 0 });
+        assert_eq!(Position::new(2, 3, 48).to_time(&map), Time { ticks: 624 });
+        // Beats in 7/8 are eighth notes:
+        assert_eq!(Position::new(3, 2, 0).to_time(&map), Time { ticks: 816 });
+    }
+
+    #[test]
+    fn test_from_time() {
+        let map = meter_map();
+        assert_eq!(
+            Position::from_time(Time { ticks: 624 }, &map),
+            Position::new(2, 3, 48)
+        );
+        assert_eq!(
+            Position::from_time(Time { ticks: 816 + 47 }, &map),
+            Position::new(3, 2, 47)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let map = meter_map();
+        for ticks in (0..2000).step_by(7) {
+            let time = Time { ticks };
+            assert_eq!(Position::from_time(time, &map).to_time(&map), time);
+        }
+    }
+
+    #[test]
+    fn test_pickup() {
+        let mut map = meter_map();
+        map.set_pickup(Time { ticks: 144 }).unwrap();
+        // A dotted quarter pickup starts halfway through beat 3:
+        assert_eq!(Position::new(0, 3, 48).to_time(&map), Time { ticks: 0 });
+        assert_eq!(Position::new(0, 4, 0).to_time(&map), Time { ticks: 48 });
+        assert_eq!(Position::new(1, 1, 0).to_time(&map), Time { ticks: 144 });
+        assert_eq!(
+            Position::from_time(Time { ticks: 0 }, &map),
+            Position::new(0, 3, 48)
+        );
+        for ticks in (0..2000).step_by(7) {
+            let time = Time { ticks };
+            assert_eq!(Position::from_time(time, &map).to_time(&map), time);
+        }
+        assert_eq!("0.4".parse::<Position>().unwrap(), Position::new(0, 4, 0));
+
+        // Beat 1 of the pickup bar is before the start of the sequence:
+        let early = Position::new(0, 1, 0);
+        assert_eq!(early.to_time(&map), Time { ticks: 0 });
+        assert!(early.checked_to_time(&map).is_err());
+        assert!(early.into_time(&map).is_err());
+        assert!(Position::new(u32::MAX, 1, 0).checked_to_time(&map).is_err());
+        assert_eq!(
+            Position::new(0, 3, 48).checked_to_time(&map).unwrap(),
+            Time { ticks: 0 }
+        );
+    }
+
+    #[test]
+    fn test_display_and_parse() {
+        let position: Position = "12.3.48".parse().unwrap();
+        assert_eq!(position, Position::new(12, 3, 48));
+        assert_eq!(position.to_string(), "12.3.48");
+        assert_eq!("5".parse::<Position>().unwrap(), Position::new(5, 1, 0));
+        assert_eq!("5.2".parse::<Position>().unwrap(), Position::new(5, 2, 0));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("1.2.3.4".parse::<Position>().is_err());
+        assert!("a.1.0".parse::<Position>().is_err());
+        assert!("1.0.0".parse::<Position>().is_err());
+        assert!("".parse::<Position>().is_err());
+    }
+}
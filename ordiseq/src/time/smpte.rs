@@ -0,0 +1,223 @@
+use crate::error::OrdiseqError;
+use crate::time::{TempoMap, Time};
+use midly::Fps;
+use std::fmt;
+use std::time::Duration;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+/// SMPTE subframes are hundredths of a frame.
+const SUBFRAMES_PER_FRAME: u128 = 100;
+
+/// The SMPTE frame rates supported by MIDI files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameRate {
+    Fps24,
+    Fps25,
+    /// 29.97 (30 / 1.001) frames per second, with drop-frame numbering.
+    Fps29_97DropFrame,
+    Fps30,
+}
+
+impl FrameRate {
+    /// Returns the exact frame rate as a (numerator, denominator) pair.
+    fn ratio(&self) -> (u128, u128) {
+        match self {
+            FrameRate::Fps24 => (24, 1),
+            FrameRate::Fps25 => (25, 1),
+            FrameRate::Fps29_97DropFrame => (30_000, 1_001),
+            FrameRate::Fps30 => (30, 1),
+        }
+    }
+
+    /// Returns the frame rate in frames per second.
+    pub fn fps(&self) -> f64 {
+        let (numerator, denominator) = self.ratio();
+        numerator as f64 / denominator as f64
+    }
+
+    /// Returns the number of whole subdivisions of a frame that fit
+    /// in a duration, where each frame has `subdivisions` parts.
+    pub(crate) fn count(&self, duration: Duration, subdivisions: u32) -> u128 {
+        let (numerator, denominator) = self.ratio();
+        duration.as_nanos() * numerator * subdivisions as u128 / (denominator * NANOS_PER_SECOND)
+    }
+
+    pub fn to_midi(&self) -> Fps {
+        match self {
+            FrameRate::Fps24 => Fps::Fps24,
+            FrameRate::Fps25 => Fps::Fps25,
+            FrameRate::Fps29_97DropFrame => Fps::Fps29,
+            FrameRate::Fps30 => Fps::Fps30,
+        }
+    }
+}
+
+/// Represents an SMPTE timecode: hours, minutes, seconds, frames and
+/// hundredths of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Timecode {
+    pub hours: u32,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub subframes: u8,
+    pub frame_rate: FrameRate,
+}
+
+impl Timecode {
+    /// Converts an elapsed wall clock time into a timecode, rounding
+    /// down to the subframe.
+    ///
+    /// At 29.97 fps the frames are numbered with drop-frame timecode:
+    /// frames 0 and 1 are skipped at the start of every minute, except
+    /// for every tenth minute.
+    pub fn from_duration(duration: Duration, frame_rate: FrameRate) -> Self {
+        let subframes = frame_rate.count(duration, SUBFRAMES_PER_FRAME as u32);
+        let mut frame_number = subframes / SUBFRAMES_PER_FRAME;
+
+        let nominal_fps = match frame_rate {
+            FrameRate::Fps24 => 24,
+            FrameRate::Fps25 => 25,
+            FrameRate::Fps29_97DropFrame | FrameRate::Fps30 => 30,
+        };
+        if frame_rate == FrameRate::Fps29_97DropFrame {
+            const FRAMES_PER_TEN_MINUTES: u128 = 17_982;
+            const FRAMES_PER_MINUTE: u128 = 1_798;
+            let tens = frame_number / FRAMES_PER_TEN_MINUTES;
+            let rest = frame_number % FRAMES_PER_TEN_MINUTES;
+            frame_number += 18 * tens;
+            if rest > 1 {
+                frame_number += 2 * ((rest - 2) / FRAMES_PER_MINUTE);
+            }
+        }
+
+        Timecode {
+            hours: (frame_number / (nominal_fps * 3600)) as u32,
+            minutes: (frame_number / (nominal_fps * 60) % 60) as u8,
+            seconds: (frame_number / nominal_fps % 60) as u8,
+            frames: (frame_number % nominal_fps) as u8,
+            subframes: (subframes % SUBFRAMES_PER_FRAME) as u8,
+            frame_rate,
+        }
+    }
+}
+
+impl fmt::Display for Timecode {
+    /// Formats the timecode as HH:MM:SS:FF, using ';' before the frames
+    /// for drop-frame timecode.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = match self.frame_rate {
+            FrameRate::Fps29_97DropFrame => ';',
+            _ => ':',
+        };
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours, self.minutes, self.seconds, separator, self.frames
+        )
+    }
+}
+
+impl TempoMap {
+    /// Converts a time in ticks into an SMPTE timecode.
+    pub fn to_timecode(&self, time: Time, frame_rate: FrameRate) -> Timecode {
+        Timecode::from_duration(self.to_duration(time), frame_rate)
+    }
+
+    /// Converts a time in ticks into an audio sample position, rounding
+    /// down to the sample. Returns an error if the sample rate is zero.
+    pub fn to_samples(&self, time: Time, sample_rate: u32) -> Result<u64, OrdiseqError> {
+        check_sample_rate(sample_rate)?;
+        let samples = self.to_duration(time).as_nanos() * sample_rate as u128 / NANOS_PER_SECOND;
+        u64::try_from(samples).map_err(|_| {
+            OrdiseqError::InvalidTimecode(format!(
+                "Tick {} is too far from the start at {sample_rate} samples per second",
+                time.ticks
+            ))
+        })
+    }
+
+    /// Converts an audio sample position into a time in ticks, rounding
+    /// down to the tick. Returns an error if the sample rate is zero.
+    pub fn from_samples(&self, samples: u64, sample_rate: u32) -> Result<Time, OrdiseqError> {
+        check_sample_rate(sample_rate)?;
+        let sample_rate = sample_rate as u64;
+        let nanos = (samples % sample_rate) as u128 * NANOS_PER_SECOND / sample_rate as u128;
+        Ok(self.to_time(Duration::new(samples / sample_rate, nanos as u32)))
+    }
+}
+
+fn check_sample_rate(sample_rate: u32) -> Result<(), OrdiseqError> {
+    if sample_rate == 0 {
+        return Err(OrdiseqError::InvalidTimecode(
+            "The sample rate must be at least 1".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::Tempo;
+
+    #[test]
+    fn test_timecode() {
+        let timecode = Timecode::from_duration(Duration::from_millis(3_723_540), FrameRate::Fps25);
+        assert_eq!(timecode.to_string(), "01:02:03:13");
+        assert_eq!(timecode.subframes, 50);
+    }
+
+    #[test]
+    fn test_drop_frame_timecode() {
+        let at_frame = |frame: u64| {
+            // The middle of a 29.97 fps frame:
+            let nanos = (frame * 2 + 1) * 1_001_000_000_000 / 60_000;
+            Timecode::from_duration(Duration::from_nanos(nanos), FrameRate::Fps29_97DropFrame)
+                .to_string()
+        };
+        assert_eq!(at_frame(0), "00:00:00;00");
+        assert_eq!(at_frame(1799), "00:00:59;29");
+        // Frames 0 and 1 are dropped at the start of minute 1:
+        assert_eq!(at_frame(1800), "00:01:00;02");
+        // But not at the start of minute 10:
+        assert_eq!(at_frame(17982), "00:10:00;00");
+    }
+
+    #[test]
+    fn test_tempo_map_timecode() {
+        let map = TempoMap::new(Tempo::default(), 96).unwrap();
+        // 120 BPM, 4 beats = 2 seconds:
+        assert_eq!(
+            map.to_timecode(Time { ticks: 384 }, FrameRate::Fps24)
+                .to_string(),
+            "00:00:02:00"
+        );
+        assert_eq!(
+            map.to_timecode(Time { ticks: 24 }, FrameRate::Fps30)
+                .to_string(),
+            "00:00:00:03"
+        );
+    }
+
+    #[test]
+    fn test_samples() {
+        let map = TempoMap::new(Tempo::default(), 96).unwrap();
+        assert_eq!(map.to_samples(Time { ticks: 96 }, 48_000).unwrap(), 24_000);
+        assert_eq!(
+            map.from_samples(24_000, 48_000).unwrap(),
+            Time { ticks: 96 }
+        );
+        assert_eq!(
+            map.from_samples(44_100 * 2, 44_100).unwrap(),
+            Time { ticks: 384 }
+        );
+        assert!(map.to_samples(Time { ticks: 96 }, 0).is_err());
+        assert!(map.from_samples(24_000, 0).is_err());
+        // Far past the end of the map, without overflowing:
+        assert_eq!(
+            map.from_samples(u64::MAX, 1).unwrap(),
+            Time { ticks: u32::MAX }
+        );
+    }
+}
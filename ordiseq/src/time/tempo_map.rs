@@ -0,0 +1,633 @@
+use crate::error::OrdiseqError;
+use crate::midi::check_resolution;
+use crate::time::Time;
+use midly::{MetaMessage, TrackEventKind};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::time::Duration;
+
+const NANOS_PER_MICRO: u128 = 1_000;
+const MICROS_PER_MINUTE: f64 = 60_000_000.0;
+/// The largest tempo value that fits in a MIDI `SetTempo` meta event (24 bits).
+const MAX_MICROS_PER_QUARTER_NOTE: u32 = 0xFF_FFFF;
+
+/// Represents a musical tempo.
+///
+/// The tempo is stored the same way MIDI files store it: as the
+/// number of microseconds per quarter note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tempo {
+    pub micros_per_quarter_note: u32,
+}
+
+impl Tempo {
+    /// Creates a new `Tempo` from beats (quarter notes) per minute.
+    ///
+    /// Returns an error if the BPM is not positive or cannot be
+    /// represented in a MIDI `SetTempo` event.
+    pub fn from_bpm(bpm: f64) -> Result<Self, OrdiseqError> {
+        if !bpm.is_finite() || bpm <= 0.0 {
+            return Err(OrdiseqError::InvalidTempo(format!(
+                "BPM must be a positive number: {bpm}"
+            )));
+        }
+        let micros = (MICROS_PER_MINUTE / bpm).round();
+        if micros < 1.0 || micros > MAX_MICROS_PER_QUARTER_NOTE as f64 {
+            return Err(OrdiseqError::InvalidTempo(format!(
+                "BPM is out of the range supported by MIDI: {bpm}"
+            )));
+        }
+        Ok(Tempo {
+            micros_per_quarter_note: micros as u32,
+        })
+    }
+
+    /// Returns the tempo in beats (quarter notes) per minute.
+    pub fn bpm(&self) -> f64 {
+        MICROS_PER_MINUTE / self.micros_per_quarter_note as f64
+    }
+}
+
+impl Default for Tempo {
+    /// The MIDI default tempo of 120 BPM.
+    fn default() -> Self {
+        Tempo {
+            micros_per_quarter_note: 500_000,
+        }
+    }
+}
+
+impl fmt::Display for Tempo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} BPM", self.bpm())
+    }
+}
+
+/// The shape of a gradual tempo change (accelerando or ritardando).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempoCurve {
+    /// The BPM changes by the same amount every tick.
+    Linear,
+    /// The BPM changes by the same ratio every tick.
+    Exponential,
+}
+
+/// A tempo change, which may ramp towards the tempo of the next change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TempoChange {
+    tempo: Tempo,
+    ramp: Option<TempoCurve>,
+}
+
+/// A tempo ramp over a segment of the tempo map.
+#[derive(Debug, Clone, Copy)]
+struct Ramp {
+    curve: TempoCurve,
+    to: Tempo,
+    ticks: u32,
+}
+
+/// Represents the tempo changes of a sequence.
+///
+/// A `TempoMap` always has a tempo at `Time` zero, and holds any
+/// number of tempo changes after it. Tempo changes are either
+/// immediate, or ramp towards the next tempo change along a
+/// `TempoCurve`. It converts between ticks and wall clock time using
+/// the ticks per quarter note of the sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    ticks_per_quarter_note: u32,
+    changes: BTreeMap<Time, TempoChange>,
+    ramp_step: Time,
+}
+
+impl TempoMap {
+    /// Creates a new tempo map starting with the given tempo.
+    ///
+    /// Returns an error if the resolution cannot be written to a MIDI
+    /// file header.
+    pub fn new(tempo: Tempo, ticks_per_quarter_note: u32) -> Result<Self, OrdiseqError> {
+        check_resolution(ticks_per_quarter_note)?;
+        let mut changes = BTreeMap::new();
+        changes.insert(Time { ticks: 0 }, TempoChange { tempo, ramp: None });
+        Ok(TempoMap {
+            ticks_per_quarter_note,
+            changes,
+            ramp_step: Time {
+                ticks: (ticks_per_quarter_note / 4).max(1),
+            },
+        })
+    }
+
+    /// Sets the tempo starting at a specific time, replacing any
+    /// previous tempo change at the same time.
+    pub fn set_tempo(&mut self, time: Time, tempo: Tempo) {
+        self.changes.insert(time, TempoChange { tempo, ramp: None });
+    }
+
+    /// Sets a gradual tempo change from `start` to `end`, replacing any
+    /// tempo changes in between. The tempo `to` is held after `end`.
+    pub fn set_tempo_ramp(
+        &mut self,
+        start: Time,
+        end: Time,
+        from: Tempo,
+        to: Tempo,
+        curve: TempoCurve,
+    ) -> Result<(), OrdiseqError> {
+        if end <= start {
+            return Err(OrdiseqError::InvalidTempo(format!(
+                "A tempo ramp must end after it starts: {} -> {}",
+                start.ticks, end.ticks
+            )));
+        }
+        let inside: Vec<Time> = self
+            .changes
+            .range(start..=end)
+            .map(|(time, _)| *time)
+            .collect();
+        for time in inside {
+            self.changes.remove(&time);
+        }
+        self.changes.insert(
+            start,
+            TempoChange {
+                tempo: from,
+                ramp: Some(curve),
+            },
+        );
+        self.set_tempo(end, to);
+        Ok(())
+    }
+
+    /// Sets the length of the stepped `SetTempo` events used to write
+    /// tempo ramps to MIDI files. Defaults to a sixteenth note.
+    pub fn set_ramp_step(&mut self, step: Time) {
+        self.ramp_step = Time {
+            ticks: step.ticks.max(1),
+        };
+    }
+
+    pub fn ramp_step(&self) -> Time {
+        self.ramp_step
+    }
+
+    /// Removes the tempo change at a specific time.
+    ///
+    /// The initial tempo at `Time` zero cannot be removed, use
+    /// `set_tempo` to replace it instead.
+    pub fn remove_tempo(&mut self, time: Time) -> Option<Tempo> {
+        if time.ticks == 0 {
+            return None;
+        }
+        self.changes.remove(&time).map(|change| change.tempo)
+    }
+
+    /// Returns the tempo in force at a specific time. Within a ramp,
+    /// this is the tempo at that exact tick.
+    pub fn tempo_at(&self, time: Time) -> Tempo {
+        let Some((start, change)) = self.changes.range(..=time).next_back() else {
+            return Tempo::default();
+        };
+        match self.ramp_from(*start, change) {
+            Some(ramp) => Tempo {
+                micros_per_quarter_note: clamp_micros(
+                    ramp.micros_at(change.tempo, (time.ticks - start.ticks) as f64),
+                ),
+            },
+            None => change.tempo,
+        }
+    }
+
+    /// Returns all the tempo changes in time order. Ramps are listed
+    /// with their starting tempo.
+    pub fn changes(&self) -> impl Iterator<Item = (Time, Tempo)> + '_ {
+        self.changes
+            .iter()
+            .map(|(time, change)| (*time, change.tempo))
+    }
+
+    /// Returns all the tempo ramps in time order, as (start, end, from,
+    /// to, curve).
+    pub fn ramps(&self) -> impl Iterator<Item = (Time, Time, Tempo, Tempo, TempoCurve)> + '_ {
+        self.segments().filter_map(|(start, end, tempo, ramp)| {
+            let ramp = ramp?;
+            Some((start, end?, tempo, ramp.to, ramp.curve))
+        })
+    }
+
+    pub fn ticks_per_quarter_note(&self) -> u32 {
+        self.ticks_per_quarter_note
+    }
+
+    /// Returns a copy of the tempo map at a different resolution, with
+    /// every tempo change moved to the matching tick.
+    pub fn with_resolution(&self, ticks_per_quarter_note: u32) -> Result<Self, OrdiseqError> {
+        check_resolution(ticks_per_quarter_note)?;
+        let rescale =
+            |time: Time| time.rescale(self.ticks_per_quarter_note, ticks_per_quarter_note);
+        Ok(TempoMap {
+            ticks_per_quarter_note,
+            changes: self
+                .changes
+                .iter()
+                .map(|(time, change)| (rescale(*time), *change))
+                .collect(),
+            ramp_step: Time {
+                ticks: rescale(self.ramp_step).ticks.max(1),
+            },
+        })
+    }
+
+    /// Converts a time in ticks into the elapsed wall clock time since
+    /// the start of the sequence.
+    ///
+    /// Tempo ramps are integrated exactly along their curve.
+    pub fn to_duration(&self, time: Time) -> Duration {
+        let mut nanos: u128 = 0;
+        for (start, end, tempo, ramp) in self.segments() {
+            if start >= time {
+                break;
+            }
+            let end = end.map_or(time, |end| end.min(time));
+            let ticks = end.ticks - start.ticks;
+            nanos += match ramp {
+                Some(ramp) => ramp
+                    .nanos_at(tempo, ticks as f64, self.ticks_per_quarter_note)
+                    .round() as u128,
+                None => self.segment_nanos(ticks, tempo),
+            };
+        }
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// Converts an elapsed wall clock time since the start of the
+    /// sequence into a time in ticks, rounding down to the tick.
+    pub fn to_time(&self, duration: Duration) -> Time {
+        let mut remaining = duration.as_nanos();
+        for (start, end, tempo, ramp) in self.segments() {
+            if let Some(end) = end {
+                let ticks = end.ticks - start.ticks;
+                let segment = match ramp {
+                    Some(ramp) => ramp
+                        .nanos_at(tempo, ticks as f64, self.ticks_per_quarter_note)
+                        .round() as u128,
+                    None => self.segment_nanos(ticks, tempo),
+                };
+                if remaining >= segment {
+                    remaining -= segment;
+                    continue;
+                }
+            }
+            let ticks = match ramp {
+                Some(ramp) => {
+                    let ticks = ramp.ticks_at(tempo, remaining as f64, self.ticks_per_quarter_note);
+                    // Guard against rounding errors just below a whole tick:
+                    ((ticks + 1e-6).floor() as u32).min(ramp.ticks - 1)
+                }
+                None => {
+                    let nanos_per_quarter_note =
+                        tempo.micros_per_quarter_note as u128 * NANOS_PER_MICRO;
+                    // Durations past the largest time stop there:
+                    u32::try_from(
+                        remaining * self.ticks_per_quarter_note as u128 / nanos_per_quarter_note,
+                    )
+                    .unwrap_or(u32::MAX)
+                }
+            };
+            return Time {
+                ticks: start.ticks.saturating_add(ticks),
+            };
+        }
+        unreachable!("A tempo map always has an open ended last segment")
+    }
+
+    /// Returns the `SetTempo` meta events for all the tempo changes,
+    /// as (absolute ticks, event) pairs.
+    ///
+    /// Ramps are written as a series of stepped tempo changes, one per
+    /// ramp step. Each step holds the average tempo over that step, so
+    /// the stepped tempo reaches the end of the ramp at the same wall
+    /// clock time as the curve.
+    pub(crate) fn midi_events(&self) -> Vec<(u32, TrackEventKind<'static>)> {
+        let tempo_event = |ticks: u32, micros: u32| {
+            (
+                ticks,
+                TrackEventKind::Meta(MetaMessage::Tempo(micros.into())),
+            )
+        };
+        let mut events = Vec::new();
+        for (start, _, tempo, ramp) in self.segments() {
+            let Some(ramp) = ramp else {
+                events.push(tempo_event(start.ticks, tempo.micros_per_quarter_note));
+                continue;
+            };
+            let mut step_start = 0;
+            while step_start < ramp.ticks {
+                let step_end = (step_start + self.ramp_step.ticks).min(ramp.ticks);
+                let nanos = ramp.nanos_at(tempo, step_end as f64, self.ticks_per_quarter_note)
+                    - ramp.nanos_at(tempo, step_start as f64, self.ticks_per_quarter_note);
+                let micros = nanos / NANOS_PER_MICRO as f64 * self.ticks_per_quarter_note as f64
+                    / (step_end - step_start) as f64;
+                events.push(tempo_event(start.ticks + step_start, clamp_micros(micros)));
+                step_start = step_end;
+            }
+        }
+        events
+    }
+
+    /// Returns the ramp starting with a tempo change, if the change
+    /// ramps and is followed by another change.
+    fn ramp_from(&self, start: Time, change: &TempoChange) -> Option<Ramp> {
+        let curve = change.ramp?;
+        let (end, next) = self
+            .changes
+            .range((Bound::Excluded(start), Bound::Unbounded))
+            .next()?;
+        Some(Ramp {
+            curve,
+            to: next.tempo,
+            ticks: end.ticks - start.ticks,
+        })
+    }
+
+    /// Iterates over (start, end, tempo, ramp) segments, where the last
+    /// segment has no end and never ramps.
+    fn segments(&self) -> impl Iterator<Item = (Time, Option<Time>, Tempo, Option<Ramp>)> + '_ {
+        let mut iter = self.changes.iter().peekable();
+        std::iter::from_fn(move || {
+            let (start, change) = iter.next()?;
+            let end = iter.peek().map(|(end, _)| **end);
+            Some((*start, end, change.tempo, self.ramp_from(*start, change)))
+        })
+    }
+
+    fn segment_nanos(&self, ticks: u32, tempo: Tempo) -> u128 {
+        ticks as u128 * tempo.micros_per_quarter_note as u128 * NANOS_PER_MICRO
+            / self.ticks_per_quarter_note as u128
+    }
+}
+
+impl Ramp {
+    /// Returns the microseconds per quarter note at a number of ticks
+    /// into the ramp.
+    fn micros_at(&self, from: Tempo, ticks: f64) -> f64 {
+        let from = from.micros_per_quarter_note as f64;
+        let to = self.to.micros_per_quarter_note as f64;
+        let fraction = ticks / self.ticks as f64;
+        match self.curve {
+            // Linear in BPM, which is the inverse of the stored tempo:
+            TempoCurve::Linear => 1.0 / (1.0 / from + (1.0 / to - 1.0 / from) * fraction),
+            TempoCurve::Exponential => from * (to / from).powf(fraction),
+        }
+    }
+
+    /// Returns the elapsed nanoseconds at a number of ticks into the
+    /// ramp, integrating the tempo curve.
+    fn nanos_at(&self, from: Tempo, ticks: f64, ticks_per_quarter_note: u32) -> f64 {
+        let from = from.micros_per_quarter_note as f64;
+        let to = self.to.micros_per_quarter_note as f64;
+        let length = self.ticks as f64;
+        let nanos_per_tick = NANOS_PER_MICRO as f64 / ticks_per_quarter_note as f64;
+        if from == to {
+            return from * ticks * nanos_per_tick;
+        }
+        match self.curve {
+            TempoCurve::Linear => {
+                let (rate_from, rate_to) = (1.0 / from, 1.0 / to);
+                let slope = (rate_to - rate_from) / length;
+                nanos_per_tick * ((rate_from + slope * ticks) / rate_from).ln() / slope
+            }
+            TempoCurve::Exponential => {
+                let log_ratio = (to / from).ln();
+                nanos_per_tick * from * length / log_ratio
+                    * ((log_ratio * ticks / length).exp() - 1.0)
+            }
+        }
+    }
+
+    /// Returns the number of ticks into the ramp after a number of
+    /// elapsed nanoseconds. This is the inverse of `nanos_at`.
+    fn ticks_at(&self, from: Tempo, nanos: f64, ticks_per_quarter_note: u32) -> f64 {
+        let from = from.micros_per_quarter_note as f64;
+        let to = self.to.micros_per_quarter_note as f64;
+        let length = self.ticks as f64;
+        let nanos_per_tick = NANOS_PER_MICRO as f64 / ticks_per_quarter_note as f64;
+        if from == to {
+            return nanos / (from * nanos_per_tick);
+        }
+        match self.curve {
+            TempoCurve::Linear => {
+                let (rate_from, rate_to) = (1.0 / from, 1.0 / to);
+                let slope = (rate_to - rate_from) / length;
+                rate_from * ((nanos * slope / nanos_per_tick).exp() - 1.0) / slope
+            }
+            TempoCurve::Exponential => {
+                let log_ratio = (to / from).ln();
+                length / log_ratio
+                    * (1.0 + nanos * log_ratio / (nanos_per_tick * from * length)).ln()
+            }
+        }
+    }
+}
+
+/// Rounds a tempo to whole microseconds within the range of a MIDI
+/// `SetTempo` event.
+fn clamp_micros(micros: f64) -> u32 {
+    micros
+        .round()
+        .clamp(1.0, MAX_MICROS_PER_QUARTER_NOTE as f64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tempo_from_bpm() {
+        let tempo = Tempo::from_bpm(120.0).unwrap();
+        assert_eq!(tempo, Tempo::default());
+        assert_eq!(tempo.bpm(), 120.0);
+        assert_eq!(
+            Tempo::from_bpm(60.0).unwrap().micros_per_quarter_note,
+            1_000_000
+        );
+    }
+
+    #[test]
+    fn test_tempo_from_invalid_bpm() {
+        assert!(Tempo::from_bpm(0.0).is_err());
+        assert!(Tempo::from_bpm(-10.0).is_err());
+        assert!(Tempo::from_bpm(f64::NAN).is_err());
+        assert!(Tempo::from_bpm(1.0).is_err()); // 60s per beat does not fit in 24 bits
+    }
+
+    #[test]
+    fn test_constant_tempo_conversion() {
+        let map = TempoMap::new(Tempo::default(), 96).unwrap();
+        assert_eq!(
+            map.to_duration(Time { ticks: 96 }),
+            Duration::from_millis(500)
+        );
+        assert_eq!(map.to_duration(Time { ticks: 384 }), Duration::from_secs(2));
+        assert_eq!(map.to_time(Duration::from_secs(2)), Time { ticks: 384 });
+    }
+
+    #[test]
+    fn test_tempo_changes_conversion() {
+        let mut map = TempoMap::new(Tempo::from_bpm(120.0).unwrap(), 96).unwrap();
+        // After two beats at 120 BPM (1s), switch to 60 BPM:
+        map.set_tempo(Time { ticks: 192 }, Tempo::from_bpm(60.0).unwrap());
+        assert_eq!(map.tempo_at(Time { ticks: 191 }).bpm(), 120.0);
+        assert_eq!(map.tempo_at(Time { ticks: 192 }).bpm(), 60.0);
+        assert_eq!(map.to_duration(Time { ticks: 192 }), Duration::from_secs(1));
+        assert_eq!(map.to_duration(Time { ticks: 288 }), Duration::from_secs(2));
+        assert_eq!(map.to_time(Duration::from_secs(2)), Time { ticks: 288 });
+        assert_eq!(map.to_time(Duration::from_millis(500)), Time { ticks: 96 });
+    }
+
+    #[test]
+    fn test_initial_tempo_cannot_be_removed() {
+        let mut map = TempoMap::new(Tempo::default(), 96).unwrap();
+        map.set_tempo(Time { ticks: 96 }, Tempo::from_bpm(90.0).unwrap());
+        assert_eq!(map.remove_tempo(Time { ticks: 0 }), None);
+        assert!(map.remove_tempo(Time { ticks: 96 }).is_some());
+        assert_eq!(map.changes().count(), 1);
+    }
+
+    #[test]
+    fn test_with_resolution() {
+        let mut map = TempoMap::new(Tempo::default(), 96).unwrap();
+        map.set_tempo(Time { ticks: 192 }, Tempo::from_bpm(60.0).unwrap());
+        assert!(map.with_resolution(0).is_err());
+        assert!(TempoMap::new(Tempo::default(), 0).is_err());
+        let rescaled = map.with_resolution(480).unwrap();
+        assert_eq!(rescaled.ticks_per_quarter_note(), 480);
+        assert_eq!(rescaled.tempo_at(Time { ticks: 959 }).bpm(), 120.0);
+        assert_eq!(rescaled.tempo_at(Time { ticks: 960 }).bpm(), 60.0);
+        assert_eq!(
+            rescaled.to_duration(Time { ticks: 1440 }),
+            map.to_duration(Time { ticks: 288 })
+        );
+    }
+
+    #[test]
+    fn test_midi_events() {
+        let mut map = TempoMap::new(Tempo::default(), 96).unwrap();
+        map.set_tempo(Time { ticks: 384 }, Tempo::from_bpm(60.0).unwrap());
+        let events = map.midi_events();
+        assert_eq!(
+            events,
+            vec![
+                (0, TrackEventKind::Meta(MetaMessage::Tempo(500_000.into()))),
+                (
+                    384,
+                    TrackEventKind::Meta(MetaMessage::Tempo(1_000_000.into()))
+                ),
+            ]
+        );
+    }
+
+    fn ramp_map(curve: TempoCurve) -> TempoMap {
+        let mut map = TempoMap::new(Tempo::default(), 96).unwrap();
+        // Four beats from 60 BPM to 120 BPM:
+        map.set_tempo_ramp(
+            Time { ticks: 0 },
+            Time { ticks: 384 },
+            Tempo::from_bpm(60.0).unwrap(),
+            Tempo::from_bpm(120.0).unwrap(),
+            curve,
+        )
+        .unwrap();
+        map
+    }
+
+    fn assert_close(duration: Duration, seconds: f64) {
+        assert!(
+            (duration.as_secs_f64() - seconds).abs() < 1e-6,
+            "{duration:?} != {seconds}s"
+        );
+    }
+
+    #[test]
+    fn test_linear_ramp() {
+        let map = ramp_map(TempoCurve::Linear);
+        assert_eq!(map.tempo_at(Time { ticks: 192 }).bpm().round(), 90.0);
+        assert_eq!(map.tempo_at(Time { ticks: 384 }).bpm(), 120.0);
+        // The integral of 60 / (60 + 15 x) over four beats:
+        assert_close(map.to_duration(Time { ticks: 384 }), 4.0 * 2f64.ln());
+        // Then one more beat at 120 BPM:
+        assert_close(map.to_duration(Time { ticks: 480 }), 4.0 * 2f64.ln() + 0.5);
+    }
+
+    #[test]
+    fn test_exponential_ramp() {
+        let map = ramp_map(TempoCurve::Exponential);
+        assert_eq!(map.tempo_at(Time { ticks: 192 }).bpm().round(), 85.0);
+        // The integral of 2^-(x / 4) over four beats:
+        assert_close(map.to_duration(Time { ticks: 384 }), 2.0 / 2f64.ln());
+    }
+
+    #[test]
+    fn test_ramp_round_trip() {
+        for curve in [TempoCurve::Linear, TempoCurve::Exponential] {
+            let map = ramp_map(curve);
+            for ticks in [0, 1, 95, 96, 200, 383, 384, 480] {
+                let time = Time { ticks };
+                assert_eq!(map.to_time(map.to_duration(time)), time, "{curve:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_ramp_midi_events() {
+        let mut map = ramp_map(TempoCurve::Linear);
+        map.set_ramp_step(Time { ticks: 48 });
+        let events = map.midi_events();
+        // Eight steps, then the final tempo:
+        assert_eq!(events.len(), 9);
+        assert_eq!(
+            events[8],
+            (
+                384,
+                TrackEventKind::Meta(MetaMessage::Tempo(500_000.into()))
+            )
+        );
+        // The stepped tempos take as long as the curve, give or take
+        // the rounding of each step to whole microseconds:
+        let seconds: f64 = events[..8]
+            .iter()
+            .map(|(_, kind)| match kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(micros)) => micros.as_int() as f64 / 2e6,
+                _ => unreachable!(),
+            })
+            .sum();
+        assert!((seconds - 4.0 * 2f64.ln()).abs() < 4e-6);
+    }
+
+    #[test]
+    fn test_ramp_replaces_changes() {
+        let mut map = TempoMap::new(Tempo::default(), 96).unwrap();
+        map.set_tempo(Time { ticks: 96 }, Tempo::from_bpm(90.0).unwrap());
+        map.set_tempo_ramp(
+            Time { ticks: 0 },
+            Time { ticks: 192 },
+            Tempo::default(),
+            Tempo::from_bpm(60.0).unwrap(),
+            TempoCurve::Exponential,
+        )
+        .unwrap();
+        assert_eq!(map.changes().count(), 2);
+        assert_eq!(map.ramps().count(), 1);
+        assert!(map
+            .set_tempo_ramp(
+                Time { ticks: 96 },
+                Time { ticks: 96 },
+                Tempo::default(),
+                Tempo::default(),
+                TempoCurve::Linear,
+            )
+            .is_err());
+    }
+}
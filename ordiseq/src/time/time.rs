@@ -7,39 +7,83 @@ pub struct Time {
     pub ticks: u32,
 }
 
-// Adding ticks to Time
+impl Time {
+    /// Converts the time from one resolution (ticks per quarter note)
+    /// to another, rounding to the nearest tick.
+    pub fn rescale(self, from_ticks_per_quarter_note: u32, to_ticks_per_quarter_note: u32) -> Self {
+        let from = from_ticks_per_quarter_note as u64;
+        let ticks = (self.ticks as u64 * to_ticks_per_quarter_note as u64 + from / 2) / from;
+        Self {
+            ticks: ticks as u32,
+        }
+    }
+
+    /// Returns the time moved later by a number of ticks, or `None` if
+    /// the result would be past the largest time.
+    pub fn checked_add_ticks(self, ticks: u32) -> Option<Time> {
+        Some(Time {
+            ticks: self.ticks.checked_add(ticks)?,
+        })
+    }
+
+    /// Returns the time moved earlier by a number of ticks, or `None`
+    /// if the result would be before zero.
+    pub fn checked_sub_ticks(self, ticks: u32) -> Option<Time> {
+        Some(Time {
+            ticks: self.ticks.checked_sub(ticks)?,
+        })
+    }
+
+    /// Returns the time moved later by a number of ticks, clamped to
+    /// the largest time.
+    pub fn saturating_add_ticks(self, ticks: u32) -> Time {
+        Time {
+            ticks: self.ticks.saturating_add(ticks),
+        }
+    }
+
+    /// Returns the time moved earlier by a number of ticks, clamped to
+    /// zero.
+    pub fn saturating_sub_ticks(self, ticks: u32) -> Time {
+        Time {
+            ticks: self.ticks.saturating_sub(ticks),
+        }
+    }
+}
+
+// Adding ticks to Time. Panics if the result is out of range; use
+// `checked_add_ticks` or `saturating_add_ticks` to handle that case.
 impl Add<u32> for Time {
     type Output = Self;
 
     fn add(self, rhs: u32) -> Self::Output {
-        Self {
-            ticks: self.ticks + rhs,
-        }
+        self.checked_add_ticks(rhs)
+            .unwrap_or_else(|| panic!("Time out of range: {} + {} ticks", self.ticks, rhs))
     }
 }
 
-// Subtracting ticks from Time
+// Subtracting ticks from Time. Panics if the result is out of range;
+// use `checked_sub_ticks` or `saturating_sub_ticks` to handle that case.
 impl Sub<u32> for Time {
     type Output = Self;
 
     fn sub(self, rhs: u32) -> Self::Output {
-        Self {
-            ticks: self.ticks.saturating_sub(rhs),
-        }
+        self.checked_sub_ticks(rhs)
+            .unwrap_or_else(|| panic!("Time out of range: {} - {} ticks", self.ticks, rhs))
     }
 }
 
 // Adding ticks to Time in-place
 impl AddAssign<u32> for Time {
     fn add_assign(&mut self, rhs: u32) {
-        self.ticks += rhs;
+        *self = *self + rhs;
     }
 }
 
 // Subtracting ticks from Time in-place
 impl SubAssign<u32> for Time {
     fn sub_assign(&mut self, rhs: u32) {
-        self.ticks = self.ticks.saturating_sub(rhs);
+        *self = *self - rhs;
     }
 }
 
@@ -78,3 +122,28 @@ impl DivAssign<f32> for Time {
         self.ticks = (self.ticks as f32 / rhs).round() as u32;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_arithmetic() {
+        let time = Time { ticks: 10 };
+        assert_eq!(time + 5, Time { ticks: 15 });
+        assert_eq!(time - 10, Time { ticks: 0 });
+        assert_eq!(time.checked_sub_ticks(11), None);
+        assert_eq!(time.saturating_sub_ticks(11), Time { ticks: 0 });
+        assert_eq!(Time { ticks: u32::MAX }.checked_add_ticks(1), None);
+        assert_eq!(
+            Time { ticks: u32::MAX }.saturating_add_ticks(1),
+            Time { ticks: u32::MAX }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Time out of range")]
+    fn test_sub_out_of_range_panics() {
+        let _ = Time { ticks: 10 } - 11;
+    }
+}
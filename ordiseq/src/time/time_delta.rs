@@ -0,0 +1,249 @@
+use crate::time::Time;
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+/// Represents a signed distance between two times, in ticks.
+///
+/// Unlike `Time`, a `TimeDelta` may be negative, so offsets that move
+/// notes earlier (pickups, nudges, pre-roll) can be represented without
+/// clamping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeDelta {
+    pub ticks: i64,
+}
+
+impl TimeDelta {
+    pub fn new(ticks: i64) -> Self {
+        TimeDelta { ticks }
+    }
+
+    pub fn abs(self) -> Self {
+        TimeDelta {
+            ticks: self.ticks.abs(),
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.ticks < 0
+    }
+}
+
+impl From<Time> for TimeDelta {
+    fn from(time: Time) -> Self {
+        TimeDelta {
+            ticks: time.ticks as i64,
+        }
+    }
+}
+
+impl fmt::Display for TimeDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:+} ticks", self.ticks)
+    }
+}
+
+impl Neg for TimeDelta {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        TimeDelta { ticks: -self.ticks }
+    }
+}
+
+impl Add for TimeDelta {
+    type Output = Self;
+
+    fn add(self, rhs: TimeDelta) -> Self::Output {
+        TimeDelta {
+            ticks: self.ticks + rhs.ticks,
+        }
+    }
+}
+
+impl Sub for TimeDelta {
+    type Output = Self;
+
+    fn sub(self, rhs: TimeDelta) -> Self::Output {
+        TimeDelta {
+            ticks: self.ticks - rhs.ticks,
+        }
+    }
+}
+
+impl AddAssign for TimeDelta {
+    fn add_assign(&mut self, rhs: TimeDelta) {
+        self.ticks += rhs.ticks;
+    }
+}
+
+impl SubAssign for TimeDelta {
+    fn sub_assign(&mut self, rhs: TimeDelta) {
+        self.ticks -= rhs.ticks;
+    }
+}
+
+// Scaling a TimeDelta by a float
+impl Mul<f32> for TimeDelta {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        TimeDelta {
+            ticks: (self.ticks as f64 * rhs as f64).round() as i64,
+        }
+    }
+}
+
+impl Time {
+    /// Returns the time moved by a delta, or `None` if the result would
+    /// be before the start of the sequence or past the largest time.
+    pub fn checked_add(self, delta: TimeDelta) -> Option<Time> {
+        let ticks = u32::try_from((self.ticks as i64).checked_add(delta.ticks)?).ok()?;
+        Some(Time { ticks })
+    }
+
+    /// Returns the time moved back by a delta, or `None` if the result
+    /// would be out of range.
+    pub fn checked_sub(self, delta: TimeDelta) -> Option<Time> {
+        let ticks = u32::try_from((self.ticks as i64).checked_sub(delta.ticks)?).ok()?;
+        Some(Time { ticks })
+    }
+
+    /// Returns the time moved by a delta, clamped to the range of `Time`.
+    pub fn saturating_add(self, delta: TimeDelta) -> Time {
+        let ticks = (self.ticks as i64)
+            .saturating_add(delta.ticks)
+            .clamp(0, u32::MAX as i64);
+        Time {
+            ticks: ticks as u32,
+        }
+    }
+
+    /// Returns the time moved back by a delta, clamped to the range of
+    /// `Time`.
+    pub fn saturating_sub(self, delta: TimeDelta) -> Time {
+        let ticks = (self.ticks as i64)
+            .saturating_sub(delta.ticks)
+            .clamp(0, u32::MAX as i64);
+        Time {
+            ticks: ticks as u32,
+        }
+    }
+}
+
+// The signed distance between two times
+impl Sub for Time {
+    type Output = TimeDelta;
+
+    fn sub(self, rhs: Time) -> Self::Output {
+        TimeDelta {
+            ticks: self.ticks as i64 - rhs.ticks as i64,
+        }
+    }
+}
+
+// Moving a Time by a delta. Panics if the result is out of range; use
+// `checked_add` or `saturating_add` to handle that case.
+impl Add<TimeDelta> for Time {
+    type Output = Self;
+
+    fn add(self, rhs: TimeDelta) -> Self::Output {
+        self.checked_add(rhs)
+            .unwrap_or_else(|| panic!("Time out of range: {} {}", self.ticks, rhs))
+    }
+}
+
+impl Sub<TimeDelta> for Time {
+    type Output = Self;
+
+    fn sub(self, rhs: TimeDelta) -> Self::Output {
+        self.checked_sub(rhs)
+            .unwrap_or_else(|| panic!("Time out of range: {} - {}", self.ticks, rhs))
+    }
+}
+
+impl AddAssign<TimeDelta> for Time {
+    fn add_assign(&mut self, rhs: TimeDelta) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign<TimeDelta> for Time {
+    fn sub_assign(&mut self, rhs: TimeDelta) {
+        *self = *self - rhs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_difference() {
+        let a = Time { ticks: 96 };
+        let b = Time { ticks: 240 };
+        assert_eq!(b - a, TimeDelta::new(144));
+        assert_eq!(a - b, TimeDelta::new(-144));
+        assert_eq!(a + (b - a), b);
+    }
+
+    #[test]
+    fn test_negative_offset() {
+        let time = Time { ticks: 96 };
+        assert_eq!(time + TimeDelta::new(-24), Time { ticks: 72 });
+        assert_eq!(time - TimeDelta::new(-24), Time { ticks: 120 });
+    }
+
+    #[test]
+    fn test_checked() {
+        let time = Time { ticks: 10 };
+        assert_eq!(time.checked_add(TimeDelta::new(-11)), None);
+        assert_eq!(
+            time.checked_sub(TimeDelta::new(10)),
+            Some(Time { ticks: 0 })
+        );
+        assert_eq!(
+            Time { ticks: u32::MAX }.checked_add(TimeDelta::new(1)),
+            None
+        );
+        assert_eq!(time.checked_add(TimeDelta::new(i64::MAX)), None);
+        assert_eq!(time.checked_sub(TimeDelta::new(i64::MIN)), None);
+    }
+
+    #[test]
+    fn test_saturating() {
+        let time = Time { ticks: 10 };
+        assert_eq!(time.saturating_add(TimeDelta::new(-11)), Time { ticks: 0 });
+        assert_eq!(
+            Time { ticks: u32::MAX }.saturating_sub(TimeDelta::new(-1)),
+            Time { ticks: u32::MAX }
+        );
+        assert_eq!(
+            time.saturating_add(TimeDelta::new(i64::MAX)),
+            Time { ticks: u32::MAX }
+        );
+        assert_eq!(
+            time.saturating_sub(TimeDelta::new(i64::MIN)),
+            Time { ticks: u32::MAX }
+        );
+        assert_eq!(
+            time.saturating_sub(TimeDelta::new(i64::MAX)),
+            Time { ticks: 0 }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Time out of range")]
+    fn test_out_of_range_panics() {
+        let _ = Time { ticks: 10 } + TimeDelta::new(-11);
+    }
+
+    #[test]
+    fn test_delta_arithmetic() {
+        let delta = TimeDelta::new(-48);
+        assert_eq!(delta.abs(), TimeDelta::new(48));
+        assert!(delta.is_negative());
+        assert_eq!(delta * 0.5, TimeDelta::new(-24));
+        assert_eq!(delta + TimeDelta::new(96), TimeDelta::new(48));
+        assert_eq!(delta.to_string(), "-48 ticks");
+    }
+}
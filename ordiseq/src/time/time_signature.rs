@@ -1,7 +1,66 @@
 use crate::error::OrdiseqError;
-use crate::time::Time;
+use crate::time::{NoteValue, Time};
+use midly::MetaMessage;
 use std::fmt;
 
+/// The largest number of groups in an additive time signature.
+const MAX_GROUPS: usize = 16;
+
+/// The metric weight of the first beat of a bar.
+const DOWNBEAT_WEIGHT: f32 = 1.0;
+/// The metric weight of the first beat of a group.
+const STRONG_BEAT_WEIGHT: f32 = 0.75;
+/// The metric weight of the other beats.
+const BEAT_WEIGHT: f32 = 0.5;
+/// The metric weight of anything between the beats.
+const OFFBEAT_WEIGHT: f32 = 0.25;
+
+/// The grouping of the beats of an additive time signature, such as
+/// the 2+2+3 of "2+2+3/8".
+///
+/// The groups are held in a fixed size array so that `TimeSignature`
+/// stays `Copy`. An empty grouping means the time signature is not
+/// additive.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct BeatGrouping {
+    groups: [u8; MAX_GROUPS],
+    len: u8,
+}
+
+impl BeatGrouping {
+    /// Creates a beat grouping from the number of beats in each group.
+    pub fn new(groups: &[u8]) -> Result<Self, OrdiseqError> {
+        if groups.len() > MAX_GROUPS {
+            return Err(OrdiseqError::InvalidTimeSignature(format!(
+                "An additive time signature can have at most {MAX_GROUPS} groups"
+            )));
+        }
+        if groups.contains(&0) {
+            return Err(OrdiseqError::InvalidTimeSignature(
+                "Every group must have at least one beat".to_string(),
+            ));
+        }
+        let mut grouping = BeatGrouping::default();
+        grouping.groups[..groups.len()].copy_from_slice(groups);
+        grouping.len = groups.len() as u8;
+        Ok(grouping)
+    }
+
+    /// Returns the number of beats in each group.
+    pub fn groups(&self) -> &[u8] {
+        &self.groups[..self.len as usize]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the total number of beats of the groups.
+    fn beats(&self) -> u32 {
+        self.groups().iter().map(|group| *group as u32).sum()
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 /// Represents a musical time signature.
 ///
@@ -9,10 +68,14 @@ use std::fmt;
 /// - `beats_per_bar`: The numerator, representing the number of beats in one measure.
 /// - `beat_unit`: The denominator, indicating the note value that represents one beat
 ///   (e.g., 1=whole, 2=half, 4=quarter, 8=eighth, etc.).
+///
+/// Additive time signatures like "2+2+3/8" also keep the grouping of
+/// their beats, see `grouping`.
 pub struct TimeSignature {
-    pub beats_per_bar: u8,
-    pub beat_unit: u8,
-    pub ticks_per_quarter_note: u32,
+    beats_per_bar: u8,
+    beat_unit: u8,
+    ticks_per_quarter_note: u32,
+    grouping: BeatGrouping,
 }
 
 impl TimeSignature {
@@ -21,6 +84,7 @@ impl TimeSignature {
     /// # Arguments
     ///
     /// * `input` - A string representation of the time signature in "numerator/denominator" format.
+    ///   The numerator may be additive, like "2+2+3/8", to group the beats.
     ///
     /// # Returns
     ///
@@ -31,7 +95,10 @@ impl TimeSignature {
     /// This function will return an error if:
     /// - The input string is not in the correct "numerator/denominator" format.
     /// - The numerator or denominator cannot be parsed as a valid number.
+    /// - The numerator is zero.
     /// - The denominator is not a power of two.
+    /// - A beat is not a whole number of ticks, or is shorter than a
+    ///   tick, at `ticks_per_quarter_note`.
     pub fn new(ts_str: &str, ticks_per_quarter_note: u32) -> Result<Self, OrdiseqError> {
         let parts: Vec<&str> = ts_str.split('/').collect();
         if parts.len() != 2 {
@@ -40,25 +107,190 @@ impl TimeSignature {
             ));
         }
 
-        let beats_per_bar: u8 = parts[0].parse().map_err(|_| {
-            OrdiseqError::InvalidTimeSignature("Numerator must be a valid number".to_string())
-        })?;
+        let groups = parts[0]
+            .split('+')
+            .map(|group| group.trim().parse::<u8>())
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| {
+                OrdiseqError::InvalidTimeSignature("Numerator must be a valid number".to_string())
+            })?;
+        let beats_per_bar = groups
+            .iter()
+            .try_fold(0u8, |sum, group| sum.checked_add(*group))
+            .ok_or_else(|| {
+                OrdiseqError::InvalidTimeSignature("Numerator is too large".to_string())
+            })?;
+        let grouping = match groups.len() {
+            1 => BeatGrouping::default(),
+            _ => BeatGrouping::new(&groups)?,
+        };
 
         let beat_unit: u8 = parts[1].parse().map_err(|_| {
             OrdiseqError::InvalidTimeSignature("Denominator must be a valid number".to_string())
         })?;
 
-        if !beat_unit.is_power_of_two() {
+        let time_signature = TimeSignature {
+            beats_per_bar,
+            beat_unit,
+            ticks_per_quarter_note,
+            grouping,
+        };
+        time_signature.check()?;
+        Ok(time_signature)
+    }
+
+    /// Returns a copy of the time signature at a different resolution.
+    ///
+    /// Returns an error if a beat would not be a whole number of ticks,
+    /// or would be shorter than a tick, at the new resolution.
+    pub fn with_resolution(&self, ticks_per_quarter_note: u32) -> Result<Self, OrdiseqError> {
+        let time_signature = TimeSignature {
+            ticks_per_quarter_note,
+            ..*self
+        };
+        time_signature.check()?;
+        Ok(time_signature)
+    }
+
+    /// Returns the number of beats in one bar: the numerator.
+    pub fn beats_per_bar(&self) -> u8 {
+        self.beats_per_bar
+    }
+
+    /// Returns the note value of one beat: the denominator.
+    pub fn beat_unit(&self) -> u8 {
+        self.beat_unit
+    }
+
+    pub fn ticks_per_quarter_note(&self) -> u32 {
+        self.ticks_per_quarter_note
+    }
+
+    /// Creates a time signature that is not additive, without checking
+    /// it.
+    pub(crate) fn plain(beats_per_bar: u8, beat_unit: u8, ticks_per_quarter_note: u32) -> Self {
+        TimeSignature {
+            beats_per_bar,
+            beat_unit,
+            ticks_per_quarter_note,
+            grouping: BeatGrouping::default(),
+        }
+    }
+
+    /// Checks that the bars of the time signature have a length: at
+    /// least one beat, and beats a whole number of ticks, at least one,
+    /// long at its resolution.
+    pub(crate) fn check(&self) -> Result<(), OrdiseqError> {
+        if self.beats_per_bar == 0 {
+            return Err(OrdiseqError::InvalidTimeSignature(
+                "Numerator must be at least 1".to_string(),
+            ));
+        }
+        if !self.beat_unit.is_power_of_two() {
             return Err(OrdiseqError::InvalidTimeSignature(
                 "Denominator must be a power of two".to_string(),
             ));
         }
+        let ticks_per_whole_note = self.ticks_per_quarter_note as u64 * 4;
+        if ticks_per_whole_note < self.beat_unit as u64 {
+            return Err(OrdiseqError::InvalidTimeSignature(format!(
+                "A 1/{} beat is shorter than a tick at {} ticks per quarter note",
+                self.beat_unit, self.ticks_per_quarter_note
+            )));
+        }
+        if !ticks_per_whole_note.is_multiple_of(self.beat_unit as u64) {
+            return Err(OrdiseqError::InvalidTimeSignature(format!(
+                "A 1/{} beat is not a whole number of ticks at {} ticks per quarter note",
+                self.beat_unit, self.ticks_per_quarter_note
+            )));
+        }
+        Ok(())
+    }
 
-        Ok(TimeSignature {
-            beats_per_bar,
-            beat_unit,
-            ticks_per_quarter_note,
-        })
+    /// Returns true for additive time signatures like "2+2+3/8".
+    ///
+    /// A grouping that no longer adds up to `beats_per_bar` is ignored.
+    pub fn is_additive(&self) -> bool {
+        !self.grouping.is_empty() && self.grouping.beats() == self.beats_per_bar as u32
+    }
+
+    /// Returns the grouping of an additive time signature, or an empty
+    /// grouping for other time signatures.
+    pub fn grouping(&self) -> BeatGrouping {
+        match self.is_additive() {
+            true => self.grouping,
+            false => BeatGrouping::default(),
+        }
+    }
+
+    /// Returns the number of beats in each group of the bar.
+    ///
+    /// Additive time signatures use their own grouping. Bars of up to
+    /// three beats form a single group, and multiples of three beats
+    /// (6/8, 9/8, 12/8, ...) are grouped in threes. Other bars are
+    /// grouped in twos, ending with a group of three for an odd number
+    /// of beats: 4/4 is 2+2, and 7/4 is 2+2+3.
+    pub fn groups(&self) -> Vec<u8> {
+        if self.is_additive() {
+            return self.grouping.groups().to_vec();
+        }
+        let beats = self.beats_per_bar;
+        if beats <= 3 {
+            return vec![beats];
+        }
+        if beats.is_multiple_of(3) {
+            return vec![3; beats as usize / 3];
+        }
+        let mut groups = vec![2; beats as usize / 2];
+        if !beats.is_multiple_of(2) {
+            *groups.last_mut().expect("There are at least two groups") = 3;
+        }
+        groups
+    }
+
+    /// Returns the beats (counting from 0) that start a group, where
+    /// accents and metronome clicks fall.
+    pub fn strong_beats(&self) -> Vec<u8> {
+        self.groups()
+            .iter()
+            .scan(0, |beat, group| {
+                let start = *beat;
+                *beat += group;
+                Some(start)
+            })
+            .collect()
+    }
+
+    /// Returns the offsets of the strong beats from the start of a bar.
+    pub fn strong_beat_times(&self) -> Vec<Time> {
+        self.strong_beats()
+            .into_iter()
+            .map(|beat| Time {
+                ticks: beat as u32 * self.ticks_per_beat(),
+            })
+            .collect()
+    }
+
+    /// Returns the metric weight of an offset from the start of a bar,
+    /// from 1.0 on the downbeat to 0.25 between the beats. Strong beats
+    /// weigh more than the other beats, so velocities can be weighted
+    /// to follow the grouping.
+    pub fn metric_weight(&self, offset: Time) -> f32 {
+        let bar_ticks = self.bar_time().ticks.max(1);
+        let offset = offset.ticks % bar_ticks;
+        let ticks_per_beat = self.ticks_per_beat().max(1);
+        if offset == 0 {
+            DOWNBEAT_WEIGHT
+        } else if !offset.is_multiple_of(ticks_per_beat) {
+            OFFBEAT_WEIGHT
+        } else if self
+            .strong_beats()
+            .contains(&((offset / ticks_per_beat) as u8))
+        {
+            STRONG_BEAT_WEIGHT
+        } else {
+            BEAT_WEIGHT
+        }
     }
 
     /// Calculate the length of one beat in ticks
@@ -67,11 +299,49 @@ impl TimeSignature {
             ticks: (beats * self.ticks_per_quarter_note as f32) as u32,
         }
     }
+
+    /// Calculate the exact length of a note value in ticks
+    pub fn note_time(&self, note_value: NoteValue) -> Result<Time, OrdiseqError> {
+        note_value.to_ticks(self.ticks_per_quarter_note)
+    }
+
+    /// Returns the length of one beat (of `beat_unit`) in ticks
+    pub fn ticks_per_beat(&self) -> u32 {
+        self.ticks_per_quarter_note * 4 / self.beat_unit as u32
+    }
+
+    /// Calculate the length of one bar in ticks
+    pub fn bar_time(&self) -> Time {
+        Time {
+            ticks: self.ticks_per_beat() * self.beats_per_bar as u32,
+        }
+    }
+
+    /// Returns the MIDI `TimeSignature` meta message for this time
+    /// signature. MIDI stores the denominator as a power of two.
+    pub fn to_midi_meta(&self) -> MetaMessage<'static> {
+        MetaMessage::TimeSignature(
+            self.beats_per_bar,
+            self.beat_unit.trailing_zeros() as u8,
+            24,
+            8,
+        )
+    }
 }
 
 impl fmt::Display for TimeSignature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}/{}", self.beats_per_bar, self.beat_unit)
+        if self.is_additive() {
+            let groups: Vec<String> = self
+                .grouping
+                .groups()
+                .iter()
+                .map(|group| group.to_string())
+                .collect();
+            write!(f, "{}/{}", groups.join("+"), self.beat_unit)
+        } else {
+            write!(f, "{}/{}", self.beats_per_bar, self.beat_unit)
+        }
     }
 }
 
@@ -122,7 +392,8 @@ mod tests {
             TimeSignature {
                 beats_per_bar: 4,
                 beat_unit: 4,
-                ticks_per_quarter_note: 96
+                ticks_per_quarter_note: 96,
+                grouping: BeatGrouping::default(),
             }
         );
     }
@@ -135,11 +406,99 @@ mod tests {
             TimeSignature {
                 beats_per_bar: 7,
                 beat_unit: 8,
-                ticks_per_quarter_note: 96
+                ticks_per_quarter_note: 96,
+                grouping: BeatGrouping::default(),
             }
         );
     }
 
+    #[test]
+    fn test_bar_time() {
+        assert_eq!(
+            TimeSignature::new("4/4", 96).unwrap().bar_time(),
+            Time { ticks: 384 }
+        );
+        assert_eq!(
+            TimeSignature::new("7/8", 96).unwrap().bar_time(),
+            Time { ticks: 336 }
+        );
+        assert_eq!(
+            TimeSignature::new("3/2", 96).unwrap().bar_time(),
+            Time { ticks: 576 }
+        );
+    }
+
+    #[test]
+    fn test_to_midi_meta() {
+        let ts = TimeSignature::new("6/8", 96).unwrap();
+        assert_eq!(ts.to_midi_meta(), MetaMessage::TimeSignature(6, 3, 24, 8));
+    }
+
+    #[test]
+    fn test_additive_time_signature() {
+        let ts = TimeSignature::new("2+2+3/8", 96).unwrap();
+        assert_eq!(ts.beats_per_bar, 7);
+        assert_eq!(ts.beat_unit, 8);
+        assert_eq!(ts.grouping().groups(), &[2, 2, 3]);
+        assert_eq!(ts.strong_beats(), vec![0, 2, 4]);
+        assert_eq!(ts.to_string(), "2+2+3/8");
+        assert_ne!(ts, TimeSignature::new("7/8", 96).unwrap());
+        assert_ne!(ts, TimeSignature::new("3+2+2/8", 96).unwrap());
+        // Exported with the total number of beats:
+        assert_eq!(ts.to_midi_meta(), MetaMessage::TimeSignature(7, 3, 24, 8));
+    }
+
+    #[test]
+    fn test_invalid_additive_time_signature() {
+        assert!(TimeSignature::new("2+0+3/8", 96).is_err());
+        assert!(TimeSignature::new("2++3/8", 96).is_err());
+        assert!(TimeSignature::new("200+100/8", 96).is_err());
+    }
+
+    #[test]
+    fn test_default_groups() {
+        let strong_beats = |input| TimeSignature::new(input, 96).unwrap().strong_beats();
+        assert_eq!(strong_beats("4/4"), vec![0, 2]);
+        assert_eq!(strong_beats("12/8"), vec![0, 3, 6, 9]);
+        assert_eq!(strong_beats("3/8"), vec![0]);
+        assert_eq!(strong_beats("2/2"), vec![0]);
+        assert_eq!(strong_beats("7/4"), vec![0, 2, 4]);
+        assert!(!TimeSignature::new("7/4", 96).unwrap().is_additive());
+
+        // Beats 1 and 3 of 4/4 weigh more than beats 2 and 4:
+        let ts = TimeSignature::new("4/4", 96).unwrap();
+        let weights: Vec<f32> = (0..4)
+            .map(|beat| ts.metric_weight(Time { ticks: beat * 96 }))
+            .collect();
+        assert_eq!(weights, vec![1.0, 0.5, 0.75, 0.5]);
+    }
+
+    #[test]
+    fn test_stale_grouping_is_ignored() {
+        let mut ts = TimeSignature::new("2+2+3/8", 96).unwrap();
+        ts.beats_per_bar = 8;
+        assert!(!ts.is_additive());
+        assert!(ts.grouping().is_empty());
+        assert_eq!(ts.to_string(), "8/8");
+    }
+
+    #[test]
+    fn test_metric_weight() {
+        let ts = TimeSignature::new("3+3+2/8", 96).unwrap();
+        let weight = |ticks| ts.metric_weight(Time { ticks });
+        assert_eq!(weight(0), 1.0);
+        assert_eq!(weight(48), 0.5);
+        assert_eq!(weight(144), 0.75);
+        assert_eq!(weight(288), 0.75);
+        assert_eq!(weight(24), 0.25);
+        // The next bar:
+        assert_eq!(weight(384), 1.0);
+        assert_eq!(
+            ts.strong_beat_times(),
+            vec![Time { ticks: 0 }, Time { ticks: 144 }, Time { ticks: 288 }]
+        );
+    }
+
     #[test]
     fn test_tpqn_standard_time_signature() {
         let ts = TimeSignature::new("4/4", 96).unwrap();
@@ -176,6 +535,7 @@ mod tests {
             beats_per_bar: 4,
             beat_unit: 3,
             ticks_per_quarter_note: 96,
+            grouping: BeatGrouping::default(),
         };
         assert_eq!(calculate_tpqn(ts), None);
     }
@@ -186,6 +546,7 @@ mod tests {
             beats_per_bar: 0,
             beat_unit: 4,
             ticks_per_quarter_note: 96,
+            grouping: BeatGrouping::default(),
         };
         assert_eq!(calculate_tpqn(ts), None);
     }
@@ -196,6 +557,7 @@ mod tests {
             beats_per_bar: 4,
             beat_unit: 0,
             ticks_per_quarter_note: 96,
+            grouping: BeatGrouping::default(),
         };
         assert_eq!(calculate_tpqn(ts), None);
     }
@@ -230,6 +592,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_new_empty_bar() {
+        assert_eq!(
+            TimeSignature::new("0/4", 96).unwrap_err().to_string(),
+            "Invalid time signature: Numerator must be at least 1"
+        );
+        // A 64th note is half a tick at 8 ticks per quarter note:
+        assert!(TimeSignature::new("4/64", 8).is_err());
+        assert!(TimeSignature::new("4/64", 16).is_ok());
+        // A sixteenth note is 2.5 ticks at 10 ticks per quarter note:
+        assert!(TimeSignature::new("3/16", 10).is_err());
+        assert!(TimeSignature::new("3/8", 10).is_ok());
+    }
+
+    #[test]
+    fn test_with_resolution() {
+        let ts = TimeSignature::new("2+2+3/8", 96).unwrap();
+        let rescaled = ts.with_resolution(480).unwrap();
+        assert_eq!(rescaled.ticks_per_quarter_note(), 480);
+        assert_eq!(rescaled.beats_per_bar(), 7);
+        assert_eq!(rescaled.beat_unit(), 8);
+        assert_eq!(rescaled.groups(), vec![2, 2, 3]);
+        // An eighth note is half a tick at 1 tick per quarter note:
+        assert!(ts.with_resolution(1).is_err());
+    }
+
     #[test]
     fn test_new_invalid_denominator_not_power_of_two() {
         let result = TimeSignature::new("4/3", 96);
@@ -0,0 +1,68 @@
+//! A small seeded random number generator.
+//!
+//! Used where results must be reproducible from a seed, such as
+//! humanization. The generator is SplitMix64, so the same seed always
+//! produces the same numbers on every platform and release.
+
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in the range [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a number uniformly distributed in the range [-1, 1).
+    pub fn uniform(&mut self) -> f64 {
+        self.next_f64() * 2.0 - 1.0
+    }
+
+    /// Returns a normally distributed number with a mean of 0 and a
+    /// standard deviation of 1.
+    pub fn gaussian(&mut self) -> f64 {
+        // Box-Muller transform, using (0, 1] to avoid ln(0):
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reproducible() {
+        let mut a = SeededRng::new(42);
+        let mut b = SeededRng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(SeededRng::new(1).next_u64(), SeededRng::new(2).next_u64());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = SeededRng::new(7);
+        for _ in 0..1000 {
+            let value = rng.uniform();
+            assert!((-1.0..1.0).contains(&value));
+            assert!(rng.gaussian().is_finite());
+        }
+    }
+}